@@ -2,9 +2,14 @@ extern crate extra;
 use extra::io::{fail, WriteExt};
 use extra::option::OptionalExt;
 
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::env::args;
+use std::f64::consts;
 use std::fmt;
 use std::io::{self, Write};
+use std::ops::{AddAssign, DivAssign, MulAssign, Neg, SubAssign};
 
 #[derive(Debug, Clone)]
 pub enum Token {
@@ -16,8 +21,10 @@ pub enum Token {
     OpenParen,
     CloseParen,
     Comma,
+    Assign,
     Number(String),
     Identificator(String),
+    History(String),
 }
 
 impl Token {
@@ -31,8 +38,10 @@ impl Token {
             Token::OpenParen => "OpenParen",
             Token::CloseParen => "CloseParen",
             Token::Comma => "comma",
+            Token::Assign => "Assign",
             Token::Number(_) => "Number",
             Token::Identificator(_) => "Identificator",
+            Token::History(_) => "History",
         }
     }
 }
@@ -53,14 +62,586 @@ pub enum ParseError {
     OtherError(String),
 }
 
+/// An arbitrary-precision natural number as 32-bit limbs, least significant first, without
+/// high zero limbs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Natural(Vec<u32>);
+
+impl Natural {
+    fn new(mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        Natural(limbs)
+    }
+
+    fn from_u32(n: u32) -> Self {
+        Natural::new(vec![n])
+    }
+
+    fn one() -> Self {
+        Natural::from_u32(1)
+    }
+
+    fn parse(digits: &str) -> Option<Self> {
+        let ten = Natural::from_u32(10);
+        let mut n = Natural::new(Vec::new());
+        for c in digits.chars() {
+            n = n.mul(&ten).add(&Natural::from_u32(c.to_digit(10)?));
+        }
+        Some(n)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bits(&self) -> usize {
+        match self.0.last() {
+            Some(top) => self.0.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn add(&self, other: &Natural) -> Natural {
+        let (long, short) = if self.0.len() >= other.0.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut limbs = Vec::with_capacity(long.0.len() + 1);
+        let mut carry = 0;
+        for (i, &limb) in long.0.iter().enumerate() {
+            let sum = limb as u64 + *short.0.get(i).unwrap_or(&0) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        Natural::new(limbs)
+    }
+
+    /// `self - other`, where `other` must not be larger
+    fn sub(&self, other: &Natural) -> Natural {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for (i, &limb) in self.0.iter().enumerate() {
+            let mut difference = limb as i64 - *other.0.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+            if difference < 0 {
+                difference += 1 << 32;
+                borrow = 1;
+            }
+            limbs.push(difference as u32);
+        }
+        Natural::new(limbs)
+    }
+
+    fn mul(&self, other: &Natural) -> Natural {
+        let mut limbs = vec![0; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.0.iter().enumerate() {
+                let product = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.0.len()] = carry as u32;
+        }
+        Natural::new(limbs)
+    }
+
+    fn pow(&self, mut exponent: u32) -> Natural {
+        let mut base = self.clone();
+        let mut result = Natural::one();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+
+    fn shl1(&self, low_bit: bool) -> Natural {
+        let mut carry = low_bit as u32;
+        let mut limbs: Vec<u32> = self
+            .0
+            .iter()
+            .map(|&limb| {
+                let shifted = (limb << 1) | carry;
+                carry = limb >> 31;
+                shifted
+            })
+            .collect();
+        limbs.push(carry);
+        Natural::new(limbs)
+    }
+
+    fn shr(&self, n: usize) -> Natural {
+        let (skip, bits) = (n / 32, n % 32);
+        let limbs = self.0.get(skip..).unwrap_or(&[]);
+        let shifted = limbs
+            .iter()
+            .enumerate()
+            .map(|(i, &limb)| match limbs.get(i + 1) {
+                Some(&high) if bits > 0 => (limb >> bits) | (high << (32 - bits)),
+                _ => limb >> bits,
+            })
+            .collect();
+        Natural::new(shifted)
+    }
+
+    fn divrem_small(&self, divisor: u32) -> (Natural, u32) {
+        let mut limbs = vec![0; self.0.len()];
+        let mut remainder = 0;
+        for (i, &limb) in self.0.iter().enumerate().rev() {
+            let current = (remainder << 32) | limb as u64;
+            limbs[i] = (current / divisor as u64) as u32;
+            remainder = current % divisor as u64;
+        }
+        (Natural::new(limbs), remainder as u32)
+    }
+
+    /// Long division a limb at a time, Knuth's algorithm D. `divisor` must not be zero.
+    fn divrem(&self, divisor: &Natural) -> (Natural, Natural) {
+        if divisor.0.len() == 1 {
+            let (quotient, remainder) = self.divrem_small(divisor.0[0]);
+            return (quotient, Natural::from_u32(remainder));
+        }
+        if self < divisor {
+            return (Natural::new(Vec::new()), self.clone());
+        }
+        // Shift both so the top bit of the divisor is set, which keeps each estimated
+        // quotient limb at most two too large
+        let shift = divisor.0.last().unwrap().leading_zeros();
+        let mut v = shl_limbs(&divisor.0, shift);
+        v.pop();
+        let mut u = shl_limbs(&self.0, shift);
+        let n = v.len();
+        let mut quotient = vec![0; u.len() - n];
+        for j in (0..quotient.len()).rev() {
+            let top = (u[j + n] as u64) << 32 | u[j + n - 1] as u64;
+            let mut estimate = top / v[n - 1] as u64;
+            let mut rest = top % v[n - 1] as u64;
+            while estimate >> 32 != 0
+                || estimate * v[n - 2] as u64 > (rest << 32 | u[j + n - 2] as u64)
+            {
+                estimate -= 1;
+                rest += v[n - 1] as u64;
+                if rest >> 32 != 0 {
+                    break;
+                }
+            }
+
+            // Subtract estimate * v from the window of u
+            let mut borrow = 0i64;
+            for i in 0..n {
+                let product = estimate * v[i] as u64;
+                let difference = u[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+                u[i + j] = difference as u32;
+                borrow = (product >> 32) as i64 - (difference >> 32);
+            }
+            let difference = u[j + n] as i64 - borrow;
+            u[j + n] = difference as u32;
+
+            // The estimate was one too large: add v back
+            if difference < 0 {
+                estimate -= 1;
+                let mut carry = 0;
+                for i in 0..n {
+                    let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            quotient[j] = estimate as u32;
+        }
+        u.truncate(n);
+        (Natural::new(quotient), Natural::new(u).shr(shift as usize))
+    }
+
+    fn gcd(&self, other: &Natural) -> Natural {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        while !b.is_zero() {
+            let remainder = a.divrem(&b).1;
+            a = b;
+            b = remainder;
+        }
+        a
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.0
+            .iter()
+            .rev()
+            .fold(0.0, |value, &limb| value * 4294967296.0 + limb as f64)
+    }
+}
+
+/// Shift limbs left by fewer than 32 bits, with the bits shifted out of the top as a last limb
+fn shl_limbs(limbs: &[u32], bits: u32) -> Vec<u32> {
+    let mut carry = 0;
+    let mut shifted: Vec<u32> = limbs
+        .iter()
+        .map(|&limb| {
+            let shifted = (limb << bits) | carry;
+            carry = limb.checked_shr(32 - bits).unwrap_or(0);
+            shifted
+        })
+        .collect();
+    shifted.push(carry);
+    shifted
+}
+
+impl Ord for Natural {
+    fn cmp(&self, other: &Natural) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for Natural {
+    fn partial_cmp(&self, other: &Natural) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Natural {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (quotient, chunk) = n.divrem_small(1_000_000_000);
+            chunks.push(chunk);
+            n = quotient;
+        }
+        write!(f, "{}", chunks.pop().unwrap_or(0))?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `scaled / 10^places` as a decimal, without trailing zeros
+fn decimal(negative: bool, scaled: &Natural, places: usize) -> String {
+    let mut digits = scaled.to_string();
+    if digits.len() <= places {
+        digits = "0".repeat(places + 1 - digits.len()) + &digits;
+    }
+    let (int, fraction) = digits.split_at(digits.len() - places);
+    let fraction = fraction.trim_end_matches('0');
+    let sign = if negative && !scaled.is_zero() {
+        "-"
+    } else {
+        ""
+    };
+    if fraction.is_empty() {
+        format!("{}{}", sign, int)
+    } else {
+        format!("{}{}.{}", sign, int, fraction)
+    }
+}
+
+/// An exact fraction in lowest terms
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rational {
+    negative: bool,
+    numer: Natural,
+    denom: Natural,
+}
+
+impl Rational {
+    fn new(negative: bool, numer: Natural, denom: Natural) -> Self {
+        let gcd = numer.gcd(&denom);
+        let (numer, denom) = if gcd == Natural::one() {
+            (numer, denom)
+        } else {
+            (numer.divrem(&gcd).0, denom.divrem(&gcd).0)
+        };
+        Rational {
+            negative: negative && !numer.is_zero(),
+            numer,
+            denom,
+        }
+    }
+
+    /// Parse a decimal literal such as `12`, `.5` or `3.25` exactly
+    fn parse(literal: &str) -> Option<Self> {
+        let (int, fraction) = match literal.find('.') {
+            Some(point) => (&literal[..point], &literal[point + 1..]),
+            None => (literal, ""),
+        };
+        if int.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let numer = Natural::parse(&[int, fraction].concat())?;
+        let denom = Natural::from_u32(10).pow(fraction.len() as u32);
+        Some(Rational::new(false, numer, denom))
+    }
+
+    fn is_integer(&self) -> bool {
+        self.denom == Natural::one()
+    }
+
+    fn neg(&self) -> Rational {
+        Rational {
+            negative: !self.negative && !self.numer.is_zero(),
+            ..self.clone()
+        }
+    }
+
+    fn add(&self, other: &Rational) -> Rational {
+        let a = self.numer.mul(&other.denom);
+        let b = other.numer.mul(&self.denom);
+        let (negative, numer) = if self.negative == other.negative {
+            (self.negative, a.add(&b))
+        } else if a >= b {
+            (self.negative, a.sub(&b))
+        } else {
+            (other.negative, b.sub(&a))
+        };
+        Rational::new(negative, numer, self.denom.mul(&other.denom))
+    }
+
+    fn mul(&self, other: &Rational) -> Rational {
+        Rational::new(
+            self.negative != other.negative,
+            self.numer.mul(&other.numer),
+            self.denom.mul(&other.denom),
+        )
+    }
+
+    /// `self / other`, where `other` must not be zero
+    fn div(&self, other: &Rational) -> Rational {
+        Rational::new(
+            self.negative != other.negative,
+            self.numer.mul(&other.denom),
+            self.denom.mul(&other.numer),
+        )
+    }
+
+    fn pow(&self, exponent: u32) -> Rational {
+        // Powers of coprime numbers stay coprime
+        Rational {
+            negative: self.negative && exponent % 2 == 1,
+            numer: self.numer.pow(exponent),
+            denom: self.denom.pow(exponent),
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        // Drop low bits of huge values so that neither half overflows on its own
+        let shift = self
+            .numer
+            .bits()
+            .min(self.denom.bits())
+            .saturating_sub(1000);
+        let value = self.numer.shr(shift).to_f64() / self.denom.shr(shift).to_f64();
+        if self.negative {
+            -value
+        } else {
+            value
+        }
+    }
+
+    /// Write the value as a decimal if it terminates, and as a fraction otherwise
+    fn to_exact_string(&self) -> String {
+        let mut places = [0, 0];
+        let mut rest = self.denom.clone();
+        for (factor, places) in [2, 5].iter().zip(places.iter_mut()) {
+            loop {
+                let (quotient, remainder) = rest.divrem_small(*factor);
+                if remainder != 0 {
+                    break;
+                }
+                rest = quotient;
+                *places += 1;
+            }
+        }
+        if rest == Natural::one() {
+            let places = places[0].max(places[1]);
+            let scale = Natural::from_u32(10).pow(places);
+            let scaled = self.numer.mul(&scale).divrem(&self.denom).0;
+            decimal(self.negative, &scaled, places as usize)
+        } else {
+            let sign = if self.negative { "-" } else { "" };
+            format!("{}{}/{}", sign, self.numer, self.denom)
+        }
+    }
+
+    /// Write the value rounded half away from zero to `places` decimal places
+    fn to_decimal_string(&self, places: u32) -> String {
+        let scale = Natural::from_u32(10).pow(places);
+        let (mut scaled, remainder) = self.numer.mul(&scale).divrem(&self.denom);
+        if remainder.shl1(false) >= self.denom {
+            scaled = scaled.add(&Natural::one());
+        }
+        decimal(self.negative, &scaled, places as usize)
+    }
+}
+
+/// The largest power, in bits, that exponentiation computes exactly. Reducing fractions of
+/// coprime operands this size already takes a noticeable moment.
+const MAX_EXACT_BITS: u64 = 1 << 15;
+
+/// The most decimal places --precision accepts
+const MAX_PRECISION: u32 = 4096;
+
+/// The significant digits that tell any two `f64` apart; more would only show binary noise
+const FLOAT_DIGITS: i32 = 17;
+
+/// How literals are read and results written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    /// Integer literals are exact and decimal ones are floating point
+    #[default]
+    Auto,
+    /// All literals are exact
+    Exact,
+    /// All literals are exact, and results are rounded to this many decimal places. Floating
+    /// point results are written with at most `FLOAT_DIGITS` significant digits.
+    Precision(u32),
+}
+
+/// A value that is exact until it meets a floating point one or a function
+#[derive(Clone, Debug)]
+pub enum Number {
+    Exact(Rational),
+    Float(f64),
+}
+
+impl Number {
+    fn to_f64(&self) -> f64 {
+        match *self {
+            Number::Exact(ref r) => r.to_f64(),
+            Number::Float(f) => f,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match *self {
+            Number::Exact(ref r) => r.numer.is_zero(),
+            Number::Float(f) => f == 0.0,
+        }
+    }
+
+    fn pow(&self, exponent: &Number) -> Number {
+        if let (Number::Exact(base), Number::Exact(e)) = (self, exponent) {
+            let power = match e.numer.0.len() {
+                0 => Some(0),
+                1 if e.is_integer() => Some(e.numer.0[0]),
+                _ => None,
+            };
+            if let Some(power) = power {
+                let bits = base.numer.bits().max(base.denom.bits()) as u64 * power as u64;
+                if bits <= MAX_EXACT_BITS && !(e.negative && base.numer.is_zero()) {
+                    let result = base.pow(power);
+                    if e.negative {
+                        let one = Rational::new(false, Natural::one(), Natural::one());
+                        return Number::Exact(one.div(&result));
+                    }
+                    return Number::Exact(result);
+                }
+            }
+        }
+        Number::Float(self.to_f64().powf(exponent.to_f64()))
+    }
+
+    pub fn format(&self, mode: Mode) -> String {
+        match (self, mode) {
+            (Number::Exact(r), Mode::Precision(places)) => r.to_decimal_string(places),
+            (Number::Exact(r), _) => r.to_exact_string(),
+            (Number::Float(f), Mode::Precision(places)) => {
+                // The decimal exponent of the leading digit
+                let exponent = format!("{:e}", f)
+                    .split('e')
+                    .nth(1)
+                    .and_then(|e| e.parse::<i32>().ok())
+                    .unwrap_or(0);
+                if exponent >= FLOAT_DIGITS && f.is_finite() {
+                    // Round to the significant digits and pad the integer with zeros
+                    let s = format!("{:.*e}", FLOAT_DIGITS as usize - 1, f);
+                    let (mantissa, exponent) = s.split_at(s.find('e').unwrap());
+                    let zeros = exponent[1..].parse::<i32>().unwrap() + 1 - FLOAT_DIGITS;
+                    return mantissa.replace('.', "") + &"0".repeat(zeros as usize);
+                }
+                let places = (places as i32).min((FLOAT_DIGITS - 1 - exponent).max(0));
+                let s = format!("{:.*}", places as usize, f);
+                let s = if s.contains('.') {
+                    s.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    &s
+                };
+                if s == "-0" {
+                    "0".to_owned()
+                } else {
+                    s.to_owned()
+                }
+            }
+            (Number::Float(f), _) => f.to_string(),
+        }
+    }
+}
+
+impl AddAssign for Number {
+    fn add_assign(&mut self, other: Number) {
+        *self = match (&*self, &other) {
+            (Number::Exact(a), Number::Exact(b)) => Number::Exact(a.add(b)),
+            _ => Number::Float(self.to_f64() + other.to_f64()),
+        };
+    }
+}
+
+impl SubAssign for Number {
+    fn sub_assign(&mut self, other: Number) {
+        *self += -other;
+    }
+}
+
+impl MulAssign for Number {
+    fn mul_assign(&mut self, other: Number) {
+        *self = match (&*self, &other) {
+            (Number::Exact(a), Number::Exact(b)) => Number::Exact(a.mul(b)),
+            _ => Number::Float(self.to_f64() * other.to_f64()),
+        };
+    }
+}
+
+impl DivAssign for Number {
+    fn div_assign(&mut self, other: Number) {
+        *self = match (&*self, &other) {
+            (Number::Exact(a), Number::Exact(b)) => Number::Exact(a.div(b)),
+            _ => Number::Float(self.to_f64() / other.to_f64()),
+        };
+    }
+}
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Exact(r) => Number::Exact(r.neg()),
+            Number::Float(f) => Number::Float(-f),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct IntermediateResult {
-    value: f64,
+    value: Number,
     tokens_read: usize,
 }
 
 impl IntermediateResult {
-    fn new(value: f64, tokens_read: usize) -> Self {
+    fn new(value: Number, tokens_read: usize) -> Self {
         IntermediateResult { value, tokens_read }
     }
 }
@@ -121,6 +702,20 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
         } else if c == ',' {
             tokens.push(Token::Comma);
             current_pos += 1;
+        } else if c == '=' {
+            tokens.push(Token::Assign);
+            current_pos += 1;
+        } else if c == '$'
+            && chars
+                .get(current_pos + 1)
+                .is_some_and(|c| c.is_ascii_digit())
+        {
+            let token_string: String = chars[current_pos + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            current_pos += token_string.len() + 1;
+            tokens.push(Token::History(token_string));
         } else {
             let token_string = consume_until_new_token(&chars[current_pos..]);
             return Err(ParseError::UnrecognizedToken(token_string));
@@ -152,7 +747,7 @@ fn consume_number(input: &[char]) -> String {
 fn consume_ident(input: &[char]) -> String {
     let mut ident = String::with_capacity(input.len());
     for &c in input {
-        if c.is_alphanumeric() {
+        if c.is_alphanumeric() || c == '_' {
             ident.push(c);
         } else {
             break;
@@ -170,19 +765,19 @@ fn consume_until_new_token(input: &[char]) -> String {
 }
 
 // Addition and subtraction
-pub fn e_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    let mut t1 = t_expr(token_list)?;
+pub fn e_expr(token_list: &[Token], env: &Environment) -> Result<IntermediateResult, ParseError> {
+    let mut t1 = t_expr(token_list, env)?;
     let mut index = t1.tokens_read;
 
     while index < token_list.len() {
         match token_list[index] {
             Token::Plus => {
-                let t2 = t_expr(&token_list[index + 1..])?;
+                let t2 = t_expr(&token_list[index + 1..], env)?;
                 t1.value += t2.value;
                 t1.tokens_read += t2.tokens_read + 1;
             }
             Token::Minus => {
-                let t2 = t_expr(&token_list[index + 1..])?;
+                let t2 = t_expr(&token_list[index + 1..], env)?;
                 t1.value -= t2.value;
                 t1.tokens_read += t2.tokens_read + 1;
             }
@@ -195,20 +790,20 @@ pub fn e_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
 }
 
 // Multiplication and division
-pub fn t_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    let mut f1 = f_expr(token_list)?;
+pub fn t_expr(token_list: &[Token], env: &Environment) -> Result<IntermediateResult, ParseError> {
+    let mut f1 = f_expr(token_list, env)?;
     let mut index = f1.tokens_read;
 
     while index < token_list.len() {
         match token_list[index] {
             Token::Multiply => {
-                let f2 = f_expr(&token_list[index + 1..])?;
+                let f2 = f_expr(&token_list[index + 1..], env)?;
                 f1.value *= f2.value;
                 f1.tokens_read += f2.tokens_read + 1;
             }
             Token::Divide => {
-                let f2 = f_expr(&token_list[index + 1..])?;
-                if f2.value == 0.0 {
+                let f2 = f_expr(&token_list[index + 1..], env)?;
+                if f2.value.is_zero() {
                     return Err(ParseError::OtherError("Divide by zero error".to_owned()));
                 } else {
                     f1.value /= f2.value;
@@ -224,15 +819,15 @@ pub fn t_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
 }
 
 // Exponentiation
-pub fn f_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
-    let mut fn1 = i_expr(token_list)?;
+pub fn f_expr(token_list: &[Token], env: &Environment) -> Result<IntermediateResult, ParseError> {
+    let mut fn1 = i_expr(token_list, env)?;
     let mut index = fn1.tokens_read;
     let token_len = token_list.len();
     while index < token_len {
         match token_list[index] {
             Token::Exponent => {
-                let f = f_expr(&token_list[index + 1..])?;
-                fn1.value = fn1.value.powf(f.value);
+                let f = f_expr(&token_list[index + 1..], env)?;
+                fn1.value = fn1.value.pow(&f.value);
                 fn1.tokens_read += f.tokens_read + 1;
             }
             Token::Number(ref n) => return Err(ParseError::UnexpectedToken(n.clone(), "operator")),
@@ -243,8 +838,8 @@ pub fn f_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
     Ok(fn1)
 }
 
-// Functions and variables(TODO)
-pub fn i_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
+// Functions and variables
+pub fn i_expr(token_list: &[Token], env: &Environment) -> Result<IntermediateResult, ParseError> {
     if token_list.is_empty() {
         return Err(ParseError::UnexpectedEndOfInput);
     }
@@ -253,10 +848,9 @@ pub fn i_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
             match token_list.get(1) {
                 Some(Token::OpenParen) => {}
                 _ => {
-                    return Err(ParseError::UnexpectedToken(
-                        token_list[0].to_string(),
-                        "parenthesis",
-                    ));
+                    return env
+                        .variable(ident)
+                        .map(|value| IntermediateResult::new(value, 1))
                 }
             }
             let mut i = 2;
@@ -278,7 +872,7 @@ pub fn i_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
                         ));
                     }
                 }
-                let expr = e_expr(&token_list[i..])?;
+                let expr = e_expr(&token_list[i..], env)?;
                 i += expr.tokens_read;
                 args.push(expr.value);
             }
@@ -287,6 +881,12 @@ pub fn i_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
                     "no matching close parenthesis found.".to_owned(),
                 ));
             }
+            if env.functions.contains_key(ident) {
+                return env
+                    .call(ident, args)
+                    .map(|value| IntermediateResult::new(value, i));
+            }
+            let args: Vec<f64> = args.iter().map(Number::to_f64).collect();
             macro_rules! functions_processor {
                 ($($ident: expr ; $args_count: expr => $proc: expr),*) => ({
                     match &ident[..] {
@@ -340,38 +940,22 @@ pub fn i_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
                     "tan";1 => |n:A| n[0].tan(),
                     "tanh";1 => |n:A| n[0].tanh(),
                     "trunc";1 => |n:A| n[0].trunc());
-            Ok(IntermediateResult::new(result, i))
+            Ok(IntermediateResult::new(Number::Float(result), i))
         }
-        _ => g_expr(token_list),
+        _ => g_expr(token_list, env),
     }
 }
 
 // Numbers and parenthesized expressions
-pub fn g_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
+pub fn g_expr(token_list: &[Token], env: &Environment) -> Result<IntermediateResult, ParseError> {
     if !token_list.is_empty() {
         match token_list[0] {
-            Token::Number(ref n) => n
-                .parse::<f64>()
-                .map_err(|_| ParseError::InvalidNumber(n.clone()))
-                .map(|num| IntermediateResult::new(num, 1)),
-            Token::Minus => {
-                if token_list.len() > 1 {
-                    if let Token::Number(ref n) = token_list[1] {
-                        n.parse::<f64>()
-                            .map_err(|_| ParseError::InvalidNumber(n.clone()))
-                            .map(|num| IntermediateResult::new(-1.0 * num, 2))
-                    } else {
-                        Err(ParseError::UnexpectedToken(
-                            token_list[1].to_string(),
-                            "number",
-                        ))
-                    }
-                } else {
-                    Err(ParseError::UnexpectedEndOfInput)
-                }
-            }
+            Token::Number(ref n) => env.number(n).map(|num| IntermediateResult::new(num, 1)),
+            Token::History(ref n) => env.history(n).map(|num| IntermediateResult::new(num, 1)),
+            Token::Minus => i_expr(&token_list[1..], env)
+                .map(|ir| IntermediateResult::new(-ir.value, ir.tokens_read + 1)),
             Token::OpenParen => {
-                let expr = e_expr(&token_list[1..]);
+                let expr = e_expr(&token_list[1..], env);
                 match expr {
                     Ok(ir) => {
                         let close_paren = ir.tokens_read + 1;
@@ -404,14 +988,285 @@ pub fn g_expr(token_list: &[Token]) -> Result<IntermediateResult, ParseError> {
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<String, ParseError> {
-    e_expr(&tokens).map(|answer| answer.value.to_string())
+/// Values that are always defined and cannot be assigned to
+fn constant(name: &str) -> Option<f64> {
+    match name {
+        "pi" => Some(consts::PI),
+        "e" => Some(consts::E),
+        "tau" => Some(consts::TAU),
+        "inf" => Some(f64::INFINITY),
+        "nan" => Some(f64::NAN),
+        _ => None,
+    }
+}
+
+/// How many calls of user-defined functions may be nested
+const MAX_DEPTH: usize = 64;
+
+/// Evaluate a whole expression, which must not be followed by anything
+fn evaluate(tokens: &[Token], env: &Environment) -> Result<Number, ParseError> {
+    let answer = e_expr(tokens, env)?;
+    match tokens.get(answer.tokens_read) {
+        Some(token) => Err(ParseError::UnexpectedToken(token.to_string(), "operator")),
+        None => Ok(answer.value),
+    }
+}
+
+/// Write tokens back out as an expression
+fn source(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&Token> = None;
+    // Whether the previous token is a unary minus
+    let mut negation = false;
+    for token in tokens {
+        let space = match (previous, token) {
+            (None, _) | (_, Token::CloseParen) | (_, Token::Comma) => false,
+            (Some(Token::OpenParen), _) => false,
+            (Some(Token::Identificator(_)), Token::OpenParen) => false,
+            (Some(Token::Minus), _) => !negation,
+            _ => true,
+        };
+        if space {
+            out.push(' ');
+        }
+        match *token {
+            Token::Plus => out.push('+'),
+            Token::Minus => out.push('-'),
+            Token::Divide => out.push('/'),
+            Token::Multiply => out.push('*'),
+            Token::Exponent => out.push('^'),
+            Token::OpenParen => out.push('('),
+            Token::CloseParen => out.push(')'),
+            Token::Comma => out.push(','),
+            Token::Assign => out.push('='),
+            Token::Number(ref s) | Token::Identificator(ref s) => out.push_str(s),
+            Token::History(ref n) => {
+                out.push('$');
+                out.push_str(n);
+            }
+        }
+        // A minus is unary unless it follows an operand, as in `g_expr`
+        negation = matches!(token, Token::Minus)
+            && !matches!(
+                previous,
+                Some(Token::Number(_))
+                    | Some(Token::Identificator(_))
+                    | Some(Token::History(_))
+                    | Some(Token::CloseParen)
+            );
+        previous = Some(token);
+    }
+    out
+}
+
+/// A function defined with `name(parameters) = body`
+#[derive(Clone, Debug)]
+pub struct Function {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Assigned variables, user-defined functions and the results of previous expressions,
+/// numbered from 1
+#[derive(Default)]
+pub struct Environment {
+    mode: Mode,
+    variables: HashMap<String, Number>,
+    functions: BTreeMap<String, Function>,
+    history: Vec<Number>,
+    /// The arguments of each user-defined function being called, innermost last
+    frames: RefCell<Vec<HashMap<String, Number>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    pub fn with_mode(mode: Mode) -> Self {
+        Environment {
+            mode,
+            ..Environment::default()
+        }
+    }
+
+    fn number(&self, literal: &str) -> Result<Number, ParseError> {
+        let exact = match self.mode {
+            Mode::Auto => !literal.contains('.'),
+            Mode::Exact | Mode::Precision(_) => true,
+        };
+        let number = if exact {
+            Rational::parse(literal).map(Number::Exact)
+        } else {
+            literal.parse::<f64>().ok().map(Number::Float)
+        };
+        number.ok_or_else(|| ParseError::InvalidNumber(literal.to_owned()))
+    }
+
+    fn variable(&self, name: &str) -> Result<Number, ParseError> {
+        if let Some(value) = constant(name) {
+            return Ok(Number::Float(value));
+        }
+        if let Some(value) = self.frames.borrow().last().and_then(|f| f.get(name)) {
+            return Ok(value.clone());
+        }
+        if name == "ans" {
+            return self.history.last().cloned().ok_or_else(|| {
+                ParseError::OtherError("there is no previous result for \"ans\".".to_owned())
+            });
+        }
+        self.variables.get(name).cloned().ok_or_else(|| {
+            ParseError::OtherError(format!("the variable \"{}\" is undefined.", name))
+        })
+    }
+
+    fn history(&self, number: &str) -> Result<Number, ParseError> {
+        number
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|n| self.history.get(n))
+            .cloned()
+            .ok_or_else(|| ParseError::OtherError(format!("there is no result ${}.", number)))
+    }
+
+    fn call(&self, name: &str, args: Vec<Number>) -> Result<Number, ParseError> {
+        let function = &self.functions[name];
+        if args.len() != function.parameters.len() {
+            return Err(ParseError::UnexpectedNumberOfArgs(
+                function.parameters.len(),
+                args.len(),
+            ));
+        }
+        if self.frames.borrow().len() >= MAX_DEPTH {
+            return Err(ParseError::OtherError(format!(
+                "the function \"{}\" recursed too deeply.",
+                name
+            )));
+        }
+        let frame = function.parameters.iter().cloned().zip(args).collect();
+        self.frames.borrow_mut().push(frame);
+        let result = evaluate(&function.body, self);
+        self.frames.borrow_mut().pop();
+        result
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        signature: &[Token],
+        body: &[Token],
+    ) -> Result<String, ParseError> {
+        let mut parameters: Vec<String> = Vec::new();
+        let mut expect_parameter = true;
+        for token in signature {
+            match *token {
+                Token::Identificator(ref parameter) if expect_parameter => {
+                    if constant(parameter).is_some() || parameter == "ans" {
+                        return Err(ParseError::OtherError(format!(
+                            "cannot use \"{}\" as a parameter.",
+                            parameter
+                        )));
+                    }
+                    if parameters.contains(parameter) {
+                        return Err(ParseError::OtherError(format!(
+                            "the parameter \"{}\" is repeated.",
+                            parameter
+                        )));
+                    }
+                    parameters.push(parameter.clone());
+                    expect_parameter = false;
+                }
+                Token::Comma if !expect_parameter => expect_parameter = true,
+                Token::CloseParen if !expect_parameter || parameters.is_empty() => {
+                    expect_parameter = false;
+                    break;
+                }
+                _ if expect_parameter => {
+                    return Err(ParseError::UnexpectedToken(token.to_string(), "parameter"))
+                }
+                _ => return Err(ParseError::UnexpectedToken(token.to_string(), "comma")),
+            }
+        }
+        match signature.last() {
+            Some(Token::CloseParen) if !expect_parameter => {}
+            _ => {
+                return Err(ParseError::OtherError(
+                    "no matching close parenthesis found.".to_owned(),
+                ))
+            }
+        }
+        if body.is_empty() {
+            return Err(ParseError::UnexpectedEndOfInput);
+        }
+        self.functions.insert(
+            name.to_owned(),
+            Function {
+                parameters,
+                body: body.to_vec(),
+            },
+        );
+        Ok(self.describe(name))
+    }
+
+    fn describe(&self, name: &str) -> String {
+        let function = &self.functions[name];
+        format!(
+            "{}({}) = {}",
+            name,
+            function.parameters.join(", "),
+            source(&function.body)
+        )
+    }
+
+    /// The definitions of all user-defined functions, sorted by name
+    pub fn functions(&self) -> Vec<String> {
+        self.functions
+            .keys()
+            .map(|name| self.describe(name))
+            .collect()
+    }
+
+    /// Evaluate an expression, or an assignment of one to a variable, and record the result.
+    /// A function definition is recorded and written back out instead.
+    pub fn parse(&mut self, tokens: Vec<Token>) -> Result<String, ParseError> {
+        if let (Some(Token::Identificator(name)), Some(Token::OpenParen)) =
+            (tokens.first(), tokens.get(1))
+        {
+            if let Some(assign) = tokens.iter().position(|t| matches!(t, Token::Assign)) {
+                return self.define(name, &tokens[2..assign], &tokens[assign + 1..]);
+            }
+        }
+        let (name, expr) = match (tokens.first(), tokens.get(1)) {
+            (Some(Token::Identificator(name)), Some(Token::Assign)) => {
+                if constant(name).is_some() || name == "ans" {
+                    return Err(ParseError::OtherError(format!(
+                        "cannot assign to \"{}\".",
+                        name
+                    )));
+                }
+                (Some(name.clone()), &tokens[2..])
+            }
+            _ => (None, &tokens[..]),
+        };
+        let value = evaluate(expr, self)?;
+        let result = value.format(self.mode);
+        if let Some(name) = name {
+            self.variables.insert(name, value.clone());
+        }
+        self.history.push(value);
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn parse(tokens: Vec<Token>) -> Result<String, ParseError> {
+        Environment::new().parse(tokens)
+    }
+
     #[test]
     fn simple_addition() {
         assert_eq!(tokenize("12+3").and_then(parse).unwrap(), "15");
@@ -491,10 +1346,187 @@ mod test {
             "14"
         );
     }
+
+    #[test]
+    fn constants() {
+        assert_eq!(tokenize("pi").and_then(parse).unwrap(), "3.141592653589793");
+        assert_eq!(tokenize("tau/2-pi").and_then(parse).unwrap(), "0");
+        assert_eq!(tokenize("-inf").and_then(parse).unwrap(), "-inf");
+        assert_eq!(tokenize("ln(e)").and_then(parse).unwrap(), "1");
+        assert!(tokenize("pi = 3").and_then(parse).is_err());
+    }
+
+    #[test]
+    fn variables() {
+        let mut env = Environment::new();
+        let mut eval = |input| tokenize(input).and_then(|tokens| env.parse(tokens));
+        assert_eq!(eval("x = 3*4").unwrap(), "12");
+        assert_eq!(eval("my_var = x+1").unwrap(), "13");
+        assert_eq!(eval("-x^2/my_var").unwrap(), "144/13");
+        assert!(eval("y").is_err());
+        assert!(eval("x = ").is_err());
+        assert!(eval("1 = x").is_err());
+    }
+
+    #[test]
+    fn history() {
+        let mut env = Environment::new();
+        let mut eval = |input| tokenize(input).and_then(|tokens| env.parse(tokens));
+        assert!(eval("ans").is_err());
+        assert_eq!(eval("2+3").unwrap(), "5");
+        assert_eq!(eval("ans*2").unwrap(), "10");
+        assert_eq!(eval("$1+$2").unwrap(), "15");
+        assert_eq!(eval("ans").unwrap(), "15");
+        assert!(eval("$5").is_err());
+        assert!(eval("$0").is_err());
+    }
+
+    #[test]
+    fn exact_integers() {
+        assert_eq!(tokenize("1/3").and_then(parse).unwrap(), "1/3");
+        assert_eq!(tokenize("1/3*3").and_then(parse).unwrap(), "1");
+        assert_eq!(tokenize("-7/8").and_then(parse).unwrap(), "-0.875");
+        assert_eq!(tokenize("2^-2").and_then(parse).unwrap(), "0.25");
+        assert_eq!(tokenize("(-2/3)^-3").and_then(parse).unwrap(), "-3.375");
+        assert_eq!(
+            tokenize("2^100-1").and_then(parse).unwrap(),
+            "1267650600228229401496703205375"
+        );
+        assert_eq!(
+            tokenize("(3^80+1)/(3^40+7)").and_then(parse).unwrap(),
+            "73904414707172961658041605103191648801/6078832729528464404"
+        );
+        assert_eq!(tokenize("10^40/10^38").and_then(parse).unwrap(), "100");
+        assert_eq!(
+            tokenize("0.1+0.2").and_then(parse).unwrap(),
+            "0.30000000000000004"
+        );
+        assert_eq!(tokenize("1/2+0.25").and_then(parse).unwrap(), "0.75");
+        assert_eq!(
+            tokenize("2^0.5").and_then(parse).unwrap(),
+            "1.4142135623730951"
+        );
+    }
+
+    #[test]
+    fn long_division() {
+        let mut seed = 1u64;
+        let mut random = |limbs: usize| {
+            let mut n = Vec::new();
+            for _ in 0..limbs {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                n.push((seed >> 32) as u32);
+            }
+            Natural::new(n)
+        };
+        let mut cases = vec![(
+            // The first estimate is one too large and v has to be added back
+            Natural::new(vec![0, 0, 0x8000_0000, 0x7fff_ffff]),
+            Natural::new(vec![1, 0, 0x8000_0000]),
+        )];
+        for &(n, d) in &[(2, 2), (5, 2), (9, 4), (40, 3), (64, 31)] {
+            cases.push((random(n), random(d)));
+        }
+        for (n, d) in cases {
+            let (quotient, remainder) = n.divrem(&d);
+            assert!(remainder < d);
+            assert!(quotient.mul(&d).add(&remainder) == n);
+        }
+    }
+
+    #[test]
+    fn large_exact_values_are_fast() {
+        use std::time::{Duration, Instant};
+        // Coprime operands just under MAX_EXACT_BITS make for the longest gcd
+        let start = Instant::now();
+        let sum = tokenize("1/3^16000+1/7^10900").and_then(parse).unwrap();
+        assert!(sum.len() > 20000 && sum.contains('/'));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn exact_mode() {
+        let mut env = Environment::with_mode(Mode::Exact);
+        let mut eval = |input| tokenize(input).and_then(|tokens| env.parse(tokens));
+        assert_eq!(eval("0.1+0.2").unwrap(), "0.3");
+        assert_eq!(eval("1.5/0.9").unwrap(), "5/3");
+        assert_eq!(eval(".5^2").unwrap(), "0.25");
+        assert!(eval(".").is_err());
+    }
+
+    #[test]
+    fn precision_mode() {
+        let mut env = Environment::with_mode(Mode::Precision(5));
+        let mut eval = |input| tokenize(input).and_then(|tokens| env.parse(tokens));
+        assert_eq!(eval("2/3").unwrap(), "0.66667");
+        assert_eq!(eval("-1/3").unwrap(), "-0.33333");
+        assert_eq!(eval("1/2").unwrap(), "0.5");
+        assert_eq!(eval("-1/10^6").unwrap(), "0");
+        assert_eq!(eval("sqrt(2)").unwrap(), "1.41421");
+        assert_eq!(eval("10^20+0.1").unwrap(), "100000000000000000000.1");
+
+        let mut env = Environment::with_mode(Mode::Precision(40));
+        let mut eval = |input| tokenize(input).and_then(|tokens| env.parse(tokens));
+        assert_eq!(eval("sqrt(2)").unwrap(), "1.4142135623730951");
+        assert_eq!(eval("sqrt(2)/1000").unwrap(), "0.0014142135623730952");
+        assert_eq!(eval("sqrt(2)*10^20").unwrap(), "141421356237309510000");
+        assert_eq!(eval("-sqrt(2)*10^17").unwrap(), "-141421356237309520");
+        assert_eq!(eval("-sqrt(1/4)").unwrap(), "-0.5");
+    }
+
+    #[test]
+    fn precision_argument() {
+        assert_eq!(parse_precision("5"), Some(5));
+        assert_eq!(parse_precision("4096"), Some(4096));
+        assert_eq!(parse_precision("4097"), None);
+        assert_eq!(parse_precision("4294967301"), None);
+        assert_eq!(parse_precision("-1"), None);
+    }
+
+    #[test]
+    fn user_functions() {
+        let mut env = Environment::new();
+        let mut eval = |input| tokenize(input).and_then(|tokens| env.parse(tokens));
+        assert_eq!(eval("f(x, y) = x^2 + -y").unwrap(), "f(x, y) = x ^ 2 + -y");
+        assert_eq!(eval("f(3, 1)").unwrap(), "8");
+        assert_eq!(eval("x = 10").unwrap(), "10");
+        assert_eq!(eval("g(y) = f(y, 1) * x").unwrap(), "g(y) = f(y, 1) * x");
+        assert_eq!(eval("g(2)").unwrap(), "30");
+        assert_eq!(eval("two() = 2").unwrap(), "two() = 2");
+        assert_eq!(eval("two()+1").unwrap(), "3");
+        assert_eq!(eval("d(x) = x - 1").unwrap(), "d(x) = x - 1");
+        assert_eq!(
+            eval("n(x) = -x - -(x-1)*-2").unwrap(),
+            "n(x) = -x - -(x - 1) * -2"
+        );
+        match eval("f(1)") {
+            Err(ParseError::UnexpectedNumberOfArgs(2, 1)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(eval("h(x, x) = x").is_err());
+        assert!(eval("h(x,) = x").is_err());
+        assert!(eval("h(pi) = 1").is_err());
+        assert!(eval("h(x) = ").is_err());
+        assert_eq!(eval("loop(x) = loop(x)").unwrap(), "loop(x) = loop(x)");
+        assert!(eval("loop(1)").is_err());
+        assert_eq!(
+            env.functions(),
+            [
+                "d(x) = x - 1",
+                "f(x, y) = x ^ 2 + -y",
+                "g(y) = f(y, 1) * x",
+                "loop(x) = loop(x)",
+                "n(x) = -x - -(x - 1) * -2",
+                "two() = 2"
+            ]
+        );
+    }
 }
 
-fn eval(input: &str) -> String {
-    match tokenize(input).and_then(parse) {
+fn eval(input: &str, env: &mut Environment) -> String {
+    match tokenize(input).and_then(|tokens| env.parse(tokens)) {
         Ok(s) => s,
         Err(e) => match e {
             ParseError::InvalidNumber(s) => ["Error: Invalid number: ", s.as_str()].concat(),
@@ -518,15 +1550,42 @@ fn eval(input: &str) -> String {
     }
 }
 
+/// Parse the number of decimal places given to --precision
+fn parse_precision(places: &str) -> Option<u32> {
+    places
+        .parse()
+        .ok()
+        .filter(|&places| places <= MAX_PRECISION)
+}
+
 fn main() {
-    let args = args();
+    let mut args = args().skip(1).peekable();
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
     let mut stderr = io::stderr();
-    if args.len() > 1 {
-        let input: Vec<String> = args.skip(1).collect();
+    let mut mode = Mode::Auto;
+    loop {
+        match args.peek().map(|arg| arg.as_str()) {
+            Some("--exact") => mode = Mode::Exact,
+            Some("--precision") => {
+                args.next();
+                match args.peek().and_then(|places| parse_precision(places)) {
+                    Some(places) => mode = Mode::Precision(places),
+                    None => fail(
+                        "--precision requires a number of decimal places",
+                        &mut stderr,
+                    ),
+                }
+            }
+            _ => break,
+        }
+        args.next();
+    }
+    let mut env = Environment::with_mode(mode);
+    let input: Vec<String> = args.collect();
+    if !input.is_empty() {
         stdout
-            .writeln(eval(&input.join("")).as_bytes())
+            .writeln(eval(&input.join(""), &mut env).as_bytes())
             .try(&mut stderr);
     } else {
         loop {
@@ -540,8 +1599,16 @@ fn main() {
                 match input.trim() {
                     "" => (),
                     "exit" => break,
+                    ":funcs" => {
+                        for function in env.functions() {
+                            stdout.writeln(function.as_bytes()).try(&mut stderr);
+                        }
+                        stdout.flush().try(&mut stderr);
+                    }
                     s => {
-                        stdout.writeln(eval(s).as_bytes()).try(&mut stderr);
+                        stdout
+                            .writeln(eval(s, &mut env).as_bytes())
+                            .try(&mut stderr);
                         stdout.flush().try(&mut stderr);
                     }
                 }
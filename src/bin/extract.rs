@@ -0,0 +1,536 @@
+extern crate bzip2;
+extern crate extrautils;
+extern crate filetime;
+extern crate libflate;
+extern crate lz4_flex;
+extern crate lzma;
+extern crate tar;
+extern crate zstd;
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::{env, process};
+
+use bzip2::read::BzDecoder;
+use extrautils::date::civil_from_days;
+use extrautils::io::read_full;
+use extrautils::links::check_links;
+use extrautils::zip_archive::{Archive as ZipArchive, S_IFLNK, S_IFMT};
+use filetime::FileTime;
+use libflate::gzip::MultiDecoder as GzipDecoder;
+use lz4_flex::frame::FrameDecoder as Lz4Decoder;
+use lzma::LzmaReader;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+static MAN_PAGE: &str = /* @MANSTART{extract} */ r#"
+NAME
+    extract - extract any archive into a single directory
+
+SYNOPSIS
+    extract [-lq] [-d DIR] ARCHIVE...
+
+DESCRIPTION
+    extract recognizes zip and tar archives, tar archives compressed with gzip, bzip2, xz, zstd or
+    lz4, and single files compressed with one of these, by their contents rather than their names.
+
+    The contents are extracted into the current directory. If the archive holds a single file or
+    directory at its top, that is extracted as it is; otherwise a directory named after the
+    archive is created to hold the contents, so that an archive never spills files into the
+    current directory. When the name is taken, '-1', '-2' and so on are appended to it.
+
+    Nothing is extracted outside of the new file or directory: members with absolute names or
+    '..' components, or leading through symbolic links to outside of it, are skipped with a
+    warning. So are encrypted zip members. Members that cannot be extracted, such as zip members
+    compressed with an unsupported method, are reported and the others are still extracted.
+
+    Members get the modification time and permissions recorded in the archive, without
+    set-user-ID, set-group-ID and sticky bits.
+
+OPTIONS
+    -l
+        List the contents of the archives: the size, modification time and name of each member.
+
+    -d DIR
+        Extract into DIR instead of the current directory.
+
+    -q
+        Do not report where each archive was extracted to.
+
+    -h
+    --help
+        Print this manual page.
+
+EXIT STATUS
+    0 on success, 1 if an archive could not be read or any of its members could not be
+    extracted, 2 on invalid options.
+"#; /* @MANEND */
+
+/// Suffixes removed from the name of an archive to name what it is extracted to
+const SUFFIXES: &[&str] = &[
+    ".tar.gz", ".tgz", ".taz", ".tar.bz2", ".tbz2", ".tbz", ".tar.xz", ".txz", ".tar.zst", ".tzst",
+    ".tar.lz4", ".tar", ".zip", ".gz", ".bz2", ".xz", ".zst", ".lz4",
+];
+
+struct Options {
+    list: bool,
+    quiet: bool,
+    directory: PathBuf,
+}
+
+/// What an input turned out to hold
+enum Contents {
+    Zip,
+    Tar(Box<dyn Read>),
+    /// A single compressed file
+    File(Box<dyn Read>),
+}
+
+/// Whether a block is a tar header: it has the ustar magic, or a valid checksum as the headers
+/// of old archives do
+fn is_tar(block: &[u8]) -> bool {
+    if block.len() < 512 {
+        return false;
+    }
+    if &block[257..262] == b"ustar" {
+        return true;
+    }
+    let stored = std::str::from_utf8(&block[148..156])
+        .ok()
+        .and_then(|field| {
+            u32::from_str_radix(field.trim_matches(|c| c == ' ' || c == '\0'), 8).ok()
+        });
+    // The checksum is computed with its own field taken as spaces
+    let sum: u32 = block[..512]
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u32::from(b)
+            }
+        })
+        .sum();
+    stored == Some(sum)
+}
+
+/// Find out what an archive holds from its first bytes, and those of its decompressed data
+fn open(path: &Path) -> io::Result<Contents> {
+    let mut file = BufReader::new(File::open(path)?);
+    let magic = file.fill_buf()?.to_vec();
+    if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+        return Ok(Contents::Zip);
+    }
+
+    let (compressed, mut reader): (bool, Box<dyn Read>) =
+        if magic.starts_with(&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00]) {
+            let reader = LzmaReader::new_decompressor(file).map_err(io::Error::other)?;
+            (true, Box::new(reader))
+        } else if magic.starts_with(&[0x1F, 0x8B]) {
+            (true, Box::new(GzipDecoder::new(file)?))
+        } else if magic.starts_with(b"BZh") {
+            (true, Box::new(BzDecoder::new(file)))
+        } else if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            (true, Box::new(ZstdDecoder::new(file)?))
+        } else if magic.starts_with(&[0x04, 0x22, 0x4D, 0x18]) {
+            (true, Box::new(Lz4Decoder::new(file)))
+        } else {
+            (false, Box::new(file))
+        };
+
+    // Look at the first block of the data to tell a tar archive from a single file
+    let mut block = vec![0; 512];
+    let len = read_full(&mut reader, &mut block)?;
+    block.truncate(len);
+    let tar = is_tar(&block);
+    let reader = Box::new(io::Cursor::new(block).chain(reader));
+    if tar {
+        Ok(Contents::Tar(reader))
+    } else if compressed {
+        Ok(Contents::File(reader))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a recognized archive or compressed file",
+        ))
+    }
+}
+
+/// The name of an archive without its suffix, which names what it is extracted to
+fn stem(path: &Path) -> OsString {
+    let name = path.file_name().unwrap_or_default();
+    let bytes = name.as_bytes();
+    for suffix in SUFFIXES {
+        if bytes.len() > suffix.len()
+            && bytes[bytes.len() - suffix.len()..].eq_ignore_ascii_case(suffix.as_bytes())
+        {
+            return OsStr::from_bytes(&bytes[..bytes.len() - suffix.len()]).to_os_string();
+        }
+    }
+    name.to_os_string()
+}
+
+/// A path which does not exist yet, numbering the name if it is taken
+fn unique(path: PathBuf) -> PathBuf {
+    if fs::symlink_metadata(&path).is_err() {
+        return path;
+    }
+    (1..)
+        .map(|n| {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!("-{}", n));
+            path.with_file_name(name)
+        })
+        .find(|path| fs::symlink_metadata(path).is_err())
+        .unwrap()
+}
+
+/// Print a member in the listing format shared by all archive types
+fn print_member(size: u64, mtime: i64, name: &str) {
+    let (year, month, day) = civil_from_days(mtime.div_euclid(86400));
+    let time = mtime.rem_euclid(86400);
+    println!(
+        "{:>12}  {:04}-{:02}-{:02} {:02}:{:02}  {}",
+        size,
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        name
+    );
+}
+
+fn list(path: &Path, contents: Contents) -> io::Result<()> {
+    match contents {
+        Contents::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+            for i in 0..archive.members.len() {
+                let member = &archive.members[i];
+                let mut name = member.name.clone();
+                let (size, mtime) = (member.size, member.mtime());
+                if member.unix_mode() & S_IFMT == S_IFLNK && !member.encrypted() {
+                    let mut target = String::new();
+                    archive.open(i)?.read_to_string(&mut target)?;
+                    name = format!("{} -> {}", name, target);
+                }
+                print_member(size, mtime, &name);
+            }
+        }
+        Contents::Tar(reader) => {
+            let mut archive = tar::Archive::new(reader);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let header = entry.header();
+                let mut name = entry.path()?.to_string_lossy().into_owned();
+                if header.entry_type().is_dir() && !name.ends_with('/') {
+                    name.push('/');
+                }
+                if let Some(target) = entry.link_name()? {
+                    let arrow = if header.entry_type().is_symlink() {
+                        "->"
+                    } else {
+                        "link to"
+                    };
+                    name = format!("{} {} {}", name, arrow, target.display());
+                }
+                print_member(entry.size(), header.mtime()? as i64, &name);
+            }
+        }
+        Contents::File(mut reader) => {
+            let size = io::copy(&mut reader, &mut io::sink())?;
+            let mtime = FileTime::from_last_modification_time(&fs::metadata(path)?).unix_seconds();
+            print_member(size, mtime, &stem(path).to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+fn set_times_and_mode(path: &Path, mtime: i64, mode: u32) -> io::Result<()> {
+    let mtime = FileTime::from_unix_time(mtime, 0);
+    filetime::set_file_times(path, mtime, mtime)?;
+    // Set-user-ID, set-group-ID and sticky bits are not restored
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+/// Extract a zip member that is not a directory to `outpath`. Whatever an earlier member put
+/// there is replaced rather than written through, as it may be a link leading outside.
+fn extract_zip_member<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    outpath: &Path,
+) -> io::Result<()> {
+    if let Some(parent) = outpath.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(outpath).is_ok_and(|metadata| !metadata.is_dir()) {
+        fs::remove_file(outpath)?;
+    }
+
+    let member = &archive.members[index];
+    let (mode, mtime) = (member.unix_mode(), member.mtime());
+    let mut reader = archive.open(index)?;
+    if mode & S_IFMT == S_IFLNK {
+        // The data of a link is its target
+        let mut target = Vec::new();
+        reader.read_to_end(&mut target)?;
+        return symlink(OsStr::from_bytes(&target), outpath);
+    }
+    let result = File::create(outpath).and_then(|mut file| io::copy(&mut reader, &mut file));
+    if let Err(err) = result {
+        let _ = fs::remove_file(outpath);
+        return Err(err);
+    }
+    set_times_and_mode(outpath, mtime, mode)
+}
+
+/// Extract a zip archive into `root`, returning the number of members that could not be
+/// extracted
+fn extract_zip(path: &Path, root: &Path) -> io::Result<usize> {
+    let root = fs::canonicalize(root)?;
+    let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut failed = 0;
+    // Directory modes and times are applied last, so read-only directories can be filled
+    let mut directories = Vec::new();
+    for i in 0..archive.members.len() {
+        let member = &archive.members[i];
+        let name = member.name.clone();
+        let is_dir = member.is_dir();
+        if member.encrypted() {
+            eprintln!("extract: {}: skipping {}: encrypted", path.display(), name);
+            continue;
+        }
+        let relative = match member.relative_path() {
+            Some((relative, false))
+                if !name.split('/').any(|part| part == "..")
+                    && check_links(&root, &relative, is_dir).is_ok() =>
+            {
+                relative
+            }
+            _ => {
+                eprintln!(
+                    "extract: {}: skipping {}: outside of the destination",
+                    path.display(),
+                    name
+                );
+                continue;
+            }
+        };
+        let outpath = root.join(&relative);
+
+        let result = if is_dir {
+            let (mtime, mode) = (member.mtime(), member.unix_mode());
+            fs::create_dir_all(&outpath).map(|()| directories.push((outpath, mtime, mode)))
+        } else {
+            extract_zip_member(&mut archive, i, &outpath)
+        };
+        if let Err(err) = result {
+            eprintln!("extract: {}: {}: {}", path.display(), name, err);
+            failed += 1;
+        }
+    }
+    directories.sort_by_key(|dir| std::cmp::Reverse(dir.0.components().count()));
+    for (dir, mtime, mode) in directories {
+        if let Err(err) = set_times_and_mode(&dir, mtime, mode) {
+            eprintln!("extract: {}: {}: {}", path.display(), dir.display(), err);
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+/// Unpack a tar member into `root`, or warn about it and skip it if it would end up outside
+fn unpack_in<R: Read>(path: &Path, entry: &mut tar::Entry<R>, root: &Path) -> io::Result<()> {
+    let name = entry.path()?.into_owned();
+    let escapes = name.has_root()
+        || name.components().any(|part| part == Component::ParentDir)
+        || check_links(root, &name, entry.header().entry_type().is_dir()).is_err();
+    if escapes || !entry.unpack_in(root)? {
+        eprintln!(
+            "extract: {}: skipping {}: outside of the destination",
+            path.display(),
+            name.display()
+        );
+    }
+    Ok(())
+}
+
+/// Extract a tar archive like `tar::Archive::unpack`, but skip the members that would end up
+/// outside of `root` and go on past the ones that fail. Returns the number of members that
+/// could not be extracted.
+fn extract_tar(path: &Path, reader: Box<dyn Read>, root: &Path) -> io::Result<usize> {
+    let root = fs::canonicalize(root)?;
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_mtime(true);
+    let mut failed = 0;
+    let mut unpack = |entry: &mut tar::Entry<_>| {
+        if let Err(err) = unpack_in(path, entry, &root) {
+            eprintln!(
+                "extract: {}: {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&entry.path_bytes()),
+                err
+            );
+            failed += 1;
+        }
+    };
+    // Directories are unpacked last, innermost first, so that their modes and times stick. They
+    // have no data, so they can still be unpacked after the archive was read on.
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            directories.push(entry);
+        } else {
+            unpack(&mut entry);
+        }
+    }
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut entry in directories {
+        unpack(&mut entry);
+    }
+    Ok(failed)
+}
+
+/// Extract the contents of an archive into `temp`, returning the number of members that could
+/// not be extracted
+fn extract_contents(path: &Path, contents: Contents, temp: &Path) -> io::Result<usize> {
+    match contents {
+        Contents::Zip => extract_zip(path, temp),
+        Contents::Tar(reader) => extract_tar(path, reader, temp),
+        Contents::File(mut reader) => {
+            let outpath = temp.join(stem(path));
+            io::copy(&mut reader, &mut File::create(&outpath)?)?;
+            let metadata = fs::metadata(path)?;
+            let mtime = FileTime::from_last_modification_time(&metadata);
+            filetime::set_file_times(&outpath, mtime, mtime)?;
+            fs::set_permissions(&outpath, metadata.permissions())?;
+            Ok(0)
+        }
+    }
+}
+
+/// Extract an archive into a temporary directory, then move its single top-level entry out of
+/// it, or rename it after the archive when there are several. Returns where the contents went,
+/// and the number of members that could not be extracted.
+fn extract(path: &Path, contents: Contents, opts: &Options) -> io::Result<(PathBuf, usize)> {
+    let stem = stem(path);
+    let mut temp_name = OsString::from(".");
+    temp_name.push(&stem);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp = opts.directory.join(temp_name);
+    fs::create_dir(&temp)?;
+
+    let result = extract_contents(path, contents, &temp).and_then(|failed| {
+        let entries = fs::read_dir(&temp)?.collect::<io::Result<Vec<_>>>()?;
+        if entries.len() == 1 {
+            let target = unique(opts.directory.join(entries[0].file_name()));
+            fs::rename(entries[0].path(), &target)?;
+            fs::remove_dir(&temp)?;
+            Ok((target, failed))
+        } else {
+            let target = unique(opts.directory.join(&stem));
+            fs::rename(&temp, &target)?;
+            Ok((target, failed))
+        }
+    });
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&temp);
+    }
+    result
+}
+
+fn usage() -> ! {
+    eprintln!("usage: extract [-lq] [-d DIR] ARCHIVE...");
+    process::exit(2);
+}
+
+fn main() {
+    let mut opts = Options {
+        list: false,
+        quiet: false,
+        directory: PathBuf::from("."),
+    };
+    let mut archives = Vec::new();
+
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        let bytes = arg.as_bytes();
+        if bytes == b"--help" {
+            print!("{}", MAN_PAGE);
+            return;
+        }
+        if bytes == b"--" {
+            archives.extend(args.by_ref().map(PathBuf::from));
+            break;
+        }
+        if !bytes.starts_with(b"-") || bytes == b"-" {
+            archives.push(PathBuf::from(arg));
+            continue;
+        }
+        for (i, &c) in bytes.iter().enumerate().skip(1) {
+            match c {
+                b'l' => opts.list = true,
+                b'q' => opts.quiet = true,
+                b'd' => {
+                    // The directory is the rest of this argument or the next one
+                    opts.directory = if i + 1 < bytes.len() {
+                        PathBuf::from(OsStr::from_bytes(&bytes[i + 1..]))
+                    } else {
+                        PathBuf::from(args.next().unwrap_or_else(|| usage()))
+                    };
+                    break;
+                }
+                b'h' => {
+                    print!("{}", MAN_PAGE);
+                    return;
+                }
+                _ => {
+                    eprintln!("extract: invalid option -- '{}'", c as char);
+                    usage();
+                }
+            }
+        }
+    }
+    if archives.is_empty() {
+        usage();
+    }
+
+    let mut failed = false;
+    for (i, path) in archives.iter().enumerate() {
+        let result = open(path).and_then(|contents| {
+            if opts.list {
+                if archives.len() > 1 {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{}:", path.display());
+                }
+                list(path, contents)
+            } else {
+                let (target, failed) = extract(path, contents, &opts)?;
+                if !opts.quiet {
+                    println!("{}: extracted to {}", path.display(), target.display());
+                }
+                if failed > 0 {
+                    return Err(io::Error::other(format!(
+                        "{} member(s) could not be extracted",
+                        failed
+                    )));
+                }
+                Ok(())
+            }
+        });
+        if let Err(err) = result {
+            eprintln!("extract: {}: {}", path.display(), err);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -1,42 +1,1121 @@
-extern crate extra;
+extern crate bzip2;
+extern crate crc32fast;
+extern crate extrautils;
+extern crate filetime;
 extern crate libflate;
+extern crate lzma;
+extern crate termion;
 
-use extra::option::OptionalExt;
-use libflate::gzip::Encoder;
-use std::io::Write;
-use std::{env, fs, io, process};
+use bzip2::write::BzEncoder;
+use bzip2::{Compression as BzCompression, Decompress, Status};
+use extrautils::deflate;
+use extrautils::temp_file::TempFile;
+use filetime::FileTime;
+use libflate::deflate::{EncodeOptions as DeflateOptions, Encoder as DeflateEncoder};
+use libflate::gzip::{Decoder, EncodeOptions, Encoder, Header, HeaderBuilder};
+use libflate::lz77::{Code, DefaultLz77Encoder, Lz77Encode, Sink, MAX_DISTANCE};
+use lzma::{LzmaReader, LzmaWriter};
+use std::ffi::{CString, OsStr};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::{cmp, env, fs, io, iter, process, thread};
 
-fn main() {
-    let mut stderr = io::stderr();
+/// The compressed format, chosen by the name the program was run as.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Gzip,
+    Xz,
+    Bzip2,
+}
 
-    let mut keep = false;
-    let mut files = Vec::new();
-    for arg in env::args().skip(1) {
-        if arg == "-k" {
-            keep = true;
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Xz => "xz",
+            Format::Bzip2 => "bzip2",
+        }
+    }
+
+    /// The recognized suffixes and what each is replaced by when
+    /// decompressing. The first one is used when compressing.
+    fn suffixes(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Format::Gzip => &[
+                (".gz", ""),
+                ("-gz", ""),
+                (".z", ""),
+                ("-z", ""),
+                ("_z", ""),
+                (".tgz", ".tar"),
+                (".taz", ".tar"),
+            ],
+            Format::Xz => &[(".xz", ""), (".txz", ".tar")],
+            Format::Bzip2 => &[
+                (".bz2", ""),
+                (".bz", ""),
+                (".tbz2", ".tar"),
+                (".tbz", ".tar"),
+            ],
+        }
+    }
+
+    fn default_level(self) -> u8 {
+        match self {
+            Format::Bzip2 => 9,
+            _ => 6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Compress,
+    Decompress,
+}
+
+struct Options {
+    format: Format,
+    mode: Mode,
+    level: u8,
+    keep: bool,
+    force: bool,
+    to_stdout: bool,
+    verbose: bool,
+    quiet: bool,
+    /// Whether to save (compressing) or restore (decompressing) the original
+    /// name and time; defaults to saving but not restoring
+    name: Option<bool>,
+    test: bool,
+    list: bool,
+    suffix: String,
+    /// Number of threads deflating in parallel with -p
+    threads: usize,
+}
+
+/// Counts the bytes passing through a reader or writer, for -v.
+struct Counter<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counter<T> {
+    fn new(inner: T) -> Counter<T> {
+        Counter { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for Counter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Remembers the last eight bytes read, which are the trailer of a member
+/// once its decoder has reached the end.
+struct Tail<R> {
+    inner: R,
+    tail: [u8; 8],
+    count: u64,
+}
+
+impl<R: Read> Read for Tail<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        if count >= 8 {
+            self.tail.copy_from_slice(&buf[count - 8..count]);
         } else {
-            files.push(arg)
+            self.tail.rotate_left(count);
+            self.tail[8 - count..].copy_from_slice(&buf[..count]);
         }
+        self.count += count as u64;
+        Ok(count)
     }
+}
 
-    if files.is_empty() {
-        eprintln!("gzip: no files provided");
-        process::exit(1);
+/// The totals over every member of a compressed file.
+struct Summary {
+    compressed: u64,
+    uncompressed: u64,
+    name: Option<CString>,
+}
+
+/// Decodes a compressed file without writing the result, relying on the
+/// decoder to check the integrity of the data.
+fn scan<R: Read>(format: Format, input: R) -> io::Result<Summary> {
+    if format == Format::Gzip {
+        return scan_gzip(input);
+    }
+
+    let mut input = Counter::new(input);
+    let mut output = Counter::new(io::sink());
+    {
+        let (mut decoder, _) = decoder(format, &mut input)?;
+        io::copy(&mut decoder, &mut output)?;
     }
+    Ok(Summary {
+        compressed: input.count,
+        uncompressed: output.count,
+        name: None,
+    })
+}
+
+/// Decodes every member of a gzip stream in turn. The decoder checks the
+/// CRC32 in each trailer itself, but not the size, which is checked here.
+struct Members<R> {
+    decoder: Option<Decoder<Tail<BufReader<R>>>>,
+    /// The input once the last member was decoded
+    rest: Option<Tail<BufReader<R>>>,
+    /// The header of the first member
+    header: Header,
+    /// Bytes decoded from the current member
+    size: u64,
+}
 
-    for arg in files {
+impl<R: Read> Members<R> {
+    fn new(input: R) -> io::Result<Members<R>> {
+        let decoder = Decoder::new(Tail {
+            inner: BufReader::new(input),
+            tail: [0; 8],
+            count: 0,
+        })?;
+        Ok(Members {
+            header: decoder.header().clone(),
+            decoder: Some(decoder),
+            rest: None,
+            size: 0,
+        })
+    }
+
+    /// The number of compressed bytes read so far
+    fn compressed(&self) -> u64 {
+        match (&self.decoder, &self.rest) {
+            (Some(decoder), _) => decoder.as_inner_ref().count,
+            (None, Some(rest)) => rest.count,
+            (None, None) => 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Members<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = match self.decoder {
+                Some(ref mut decoder) => decoder.read(buf)?,
+                None => return Ok(0),
+            };
+            if len > 0 || buf.is_empty() {
+                self.size += len as u64;
+                return Ok(len);
+            }
+
+            let mut reader = self.decoder.take().unwrap().into_inner();
+            let size = u32::from_le_bytes([
+                reader.tail[4],
+                reader.tail[5],
+                reader.tail[6],
+                reader.tail[7],
+            ]);
+            if size != self.size as u32 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "length error in trailer",
+                ));
+            }
+            self.size = 0;
+
+            // Running out of input before the next header ends the stream
+            if reader.inner.fill_buf()?.is_empty() {
+                self.rest = Some(reader);
+                return Ok(0);
+            }
+            self.decoder = Some(Decoder::new(reader)?);
+        }
+    }
+}
+
+/// Decodes every member of a gzip stream without writing the result.
+fn scan_gzip<R: Read>(input: R) -> io::Result<Summary> {
+    let mut members = Members::new(input)?;
+    let mut output = Counter::new(io::sink());
+    io::copy(&mut members, &mut output)?;
+    Ok(Summary {
+        compressed: members.compressed(),
+        uncompressed: output.count,
+        name: members.header.filename().cloned(),
+    })
+}
+
+fn open_input(path: &str) -> io::Result<Box<dyn Read>> {
+    if path == "-" {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+}
+
+/// Compresses `input` into `output`; the header is only used by gzip.
+fn compress<R: Read, W: Write>(
+    input: &mut R,
+    output: W,
+    opts: &Options,
+    header: Header,
+) -> io::Result<()> {
+    match opts.format {
+        Format::Gzip => {
+            let window = deflate::window_size(u32::from(opts.level));
+            if opts.threads > 1 {
+                return compress_parallel(input, output, window, opts.threads, header);
+            }
+            let options = EncodeOptions::with_lz77(DefaultLz77Encoder::with_window_size(window))
+                .header(header);
+            let mut encoder = Encoder::with_options(output, options)?;
+            io::copy(input, &mut encoder)?;
+            encoder.finish().into_result()?.flush()
+        }
+        Format::Xz => {
+            let mut encoder =
+                LzmaWriter::new_compressor(output, opts.level as u32).map_err(io::Error::other)?;
+            io::copy(input, &mut encoder)?;
+            encoder.finish().map_err(io::Error::other)?.flush()
+        }
+        Format::Bzip2 => {
+            // The bzip2 bindings only offer three block sizes
+            let level = match opts.level {
+                1..=3 => BzCompression::Fastest,
+                4..=7 => BzCompression::Default,
+                _ => BzCompression::Best,
+            };
+            let mut encoder = BzEncoder::new(output, level);
+            io::copy(input, &mut encoder)?;
+            encoder.finish()?.flush()
+        }
+    }
+}
+
+/// Decodes concatenated bzip2 streams. Unlike the decoder of the bindings,
+/// which stops quietly, input that ends inside a stream is an error.
+struct Bunzip2<R> {
+    input: BufReader<R>,
+    data: Decompress,
+}
+
+impl<R: Read> Bunzip2<R> {
+    fn new(input: R) -> Bunzip2<R> {
+        Bunzip2 {
+            input: BufReader::new(input),
+            data: Decompress::new(false),
+        }
+    }
+}
+
+impl<R: Read> Read for Bunzip2<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (consumed, read, status) = {
+                let input = self.input.fill_buf()?;
+                if input.is_empty() {
+                    if self.data.total_in() > 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "compressed data is truncated",
+                        ));
+                    }
+                    return Ok(0);
+                }
+                let (total_in, total_out) = (self.data.total_in(), self.data.total_out());
+                let status = self
+                    .data
+                    .decompress(input, buf)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                (
+                    (self.data.total_in() - total_in) as usize,
+                    (self.data.total_out() - total_out) as usize,
+                    status,
+                )
+            };
+            self.input.consume(consumed);
+            if status == Status::StreamEnd {
+                self.data = Decompress::new(false);
+            }
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// The size of the blocks deflated in parallel by -p.
+const PARALLEL_BLOCK: usize = 128 * 1024;
+
+/// An LZ77 encoder primed with the data preceding its block, which it can
+/// refer back to without emitting codes for the dictionary itself.
+struct PrimedLz77 {
+    window: u16,
+    buf: Vec<u8>,
+    dictionary: usize,
+}
+
+impl Lz77Encode for PrimedLz77 {
+    fn encode<S: Sink>(&mut self, buf: &[u8], _sink: S) {
+        self.buf.extend_from_slice(buf);
+    }
+
+    fn flush<S: Sink>(&mut self, sink: S) {
         {
-            let output = fs::File::create(&format!("{}.gz", &arg)).try(&mut stderr);
-            let mut encoder = Encoder::new(output).try(&mut stderr);
+            let mut sink = Unprimed {
+                sink,
+                buf: &self.buf,
+                skip: self.dictionary,
+                pos: 0,
+            };
+            let mut lz77 = DefaultLz77Encoder::with_window_size(self.window);
+            lz77.encode(&self.buf, &mut sink);
+            lz77.flush(&mut sink);
+        }
+        self.buf.clear();
+        self.dictionary = 0;
+    }
+
+    fn window_size(&self) -> u16 {
+        self.window
+    }
+}
+
+/// Drops the codes covering the dictionary of a `PrimedLz77`, keeping only the
+/// part of a match that runs on into the block.
+struct Unprimed<'a, S> {
+    sink: S,
+    buf: &'a [u8],
+    skip: usize,
+    pos: usize,
+}
+
+impl<'a, S: Sink> Sink for Unprimed<'a, S> {
+    fn consume(&mut self, code: Code) {
+        let start = self.pos;
+        self.pos += match code {
+            Code::Literal(_) => 1,
+            Code::Pointer { length, .. } => length as usize,
+        };
+        if self.pos <= self.skip {
+            return;
+        }
+        if start >= self.skip {
+            self.sink.consume(code);
+            return;
+        }
+        match code {
+            Code::Pointer {
+                backward_distance, ..
+            } if self.pos - self.skip >= 3 => self.sink.consume(Code::Pointer {
+                length: (self.pos - self.skip) as u16,
+                backward_distance,
+            }),
+            _ => {
+                for &byte in &self.buf[self.skip..self.pos] {
+                    self.sink.consume(Code::Literal(byte));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the bits of a deflate stream, least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: usize) -> io::Result<usize> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos / 8).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "deflate block is truncated")
+            })?;
+            value |= ((byte >> (self.pos % 8)) as usize & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+    counts: [usize; 16],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize]] = symbol;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> io::Result<usize> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= bits.bits(1)?;
+            let count = self.counts[length];
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid Huffman code",
+        ))
+    }
+}
+
+/// Finds the bit at which the first block of a deflate stream ends. libflate
+/// pads its output to a byte, so this is where a following block must start.
+fn block_end(data: &[u8]) -> io::Result<usize> {
+    let mut bits = BitReader { data, pos: 0 };
+    bits.bits(1)?;
+    let (literals, distances) = match bits.bits(2)? {
+        1 => {
+            let mut lengths = [8; 288];
+            for (symbol, length) in lengths.iter_mut().enumerate() {
+                match symbol {
+                    144..=255 => *length = 9,
+                    256..=279 => *length = 7,
+                    _ => (),
+                }
+            }
+            (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+        }
+        2 => {
+            const ORDER: [usize; 19] = [
+                16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+            ];
+            let literal_count = bits.bits(5)? + 257;
+            let distance_count = bits.bits(5)? + 1;
+            let length_count = bits.bits(4)? + 4;
 
-            let mut input = fs::File::open(&arg).try(&mut stderr);
-            io::copy(&mut input, &mut encoder).try(&mut stderr);
+            let mut lengths = [0; 19];
+            for &i in ORDER.iter().take(length_count) {
+                lengths[i] = bits.bits(3)? as u8;
+            }
+            let code = Huffman::new(&lengths);
 
-            let mut encoded = encoder.finish().into_result().try(&mut stderr);
-            encoded.flush().try(&mut stderr);
+            let mut lengths = Vec::new();
+            while lengths.len() < literal_count + distance_count {
+                let (length, repeat) = match code.decode(&mut bits)? {
+                    16 => (*lengths.last().unwrap_or(&0), 3 + bits.bits(2)?),
+                    17 => (0, 3 + bits.bits(3)?),
+                    18 => (0, 11 + bits.bits(7)?),
+                    length => (length as u8, 1),
+                };
+                lengths.extend(iter::repeat_n(length, repeat));
+            }
+            (
+                Huffman::new(&lengths[..literal_count]),
+                Huffman::new(&lengths[literal_count..literal_count + distance_count]),
+            )
         }
-        if !keep {
-            fs::remove_file(&arg).try(&mut stderr);
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected deflate block type",
+            ))
         }
+    };
+
+    loop {
+        match literals.decode(&mut bits)? {
+            0..=255 => (),
+            256 => return Ok(bits.pos),
+            symbol => {
+                if (265..285).contains(&symbol) {
+                    bits.bits((symbol - 261) / 4)?;
+                }
+                let distance = distances.decode(&mut bits)?;
+                if distance >= 4 {
+                    bits.bits(distance / 2 - 1)?;
+                }
+            }
+        }
+    }
+}
+
+/// Deflates one block as a non-final deflate block followed by an empty stored
+/// block, which brings the stream back to a byte boundary so that the blocks
+/// can simply be concatenated.
+fn deflate_block(dictionary: &[u8], block: &[u8], window: u16) -> io::Result<Vec<u8>> {
+    let lz77 = PrimedLz77 {
+        window,
+        buf: dictionary.to_vec(),
+        dictionary: dictionary.len(),
+    };
+    let options = DeflateOptions::with_lz77(lz77).block_size(usize::MAX);
+    let mut encoder = DeflateEncoder::with_options(Vec::new(), options);
+    encoder.write_all(block)?;
+    let mut data = encoder.finish().into_result()?;
+
+    // Clear the final block bit, then append the stored block header bits and
+    // padding, and its zero length
+    let end = block_end(&data)?;
+    data[0] &= !1;
+    data.resize((end + 3).div_ceil(8), 0);
+    data.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+    Ok(data)
+}
+
+fn write_gzip_header<W: Write>(output: &mut W, header: &Header) -> io::Result<()> {
+    let flags = if header.filename().is_some() { 0x08 } else { 0 };
+    output.write_all(&[0x1F, 0x8B, 8, flags])?;
+    output.write_all(&header.modification_time().to_le_bytes())?;
+    output.write_all(&[0, 3])?;
+    if let Some(name) = header.filename() {
+        output.write_all(name.as_bytes_with_nul())?;
+    }
+    Ok(())
+}
+
+/// Compresses into a single gzip member like `compress`, but deflates blocks
+/// of the input on several threads. Each block is primed with the last 32 KiB
+/// of the one before it, so matches can still reach back across blocks.
+fn compress_parallel<R: Read, W: Write>(
+    input: &mut R,
+    mut output: W,
+    window: u16,
+    threads: usize,
+    header: Header,
+) -> io::Result<()> {
+    write_gzip_header(&mut output, &header)?;
+
+    let mut crc = crc32fast::Hasher::new();
+    let mut size = 0u64;
+    let mut dictionary = Vec::new();
+    loop {
+        let mut blocks = Vec::with_capacity(threads);
+        while blocks.len() < threads {
+            let mut block = Vec::with_capacity(PARALLEL_BLOCK);
+            input
+                .by_ref()
+                .take(PARALLEL_BLOCK as u64)
+                .read_to_end(&mut block)?;
+            if block.is_empty() {
+                break;
+            }
+            blocks.push(block);
+        }
+        if blocks.is_empty() {
+            break;
+        }
+
+        let deflated = thread::scope(|scope| {
+            let handles = blocks
+                .iter()
+                .enumerate()
+                .map(|(i, block)| {
+                    let previous = if i == 0 { &dictionary } else { &blocks[i - 1] };
+                    let start = previous.len().saturating_sub(MAX_DISTANCE as usize);
+                    let previous = &previous[start..];
+                    scope.spawn(move || deflate_block(previous, block, window))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("deflate thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        for (block, data) in blocks.iter().zip(deflated) {
+            output.write_all(&data?)?;
+            crc.update(block);
+            size += block.len() as u64;
+        }
+
+        let last = blocks.pop().unwrap_or_default();
+        if last.len() < PARALLEL_BLOCK {
+            break;
+        }
+        dictionary = last;
+    }
+
+    // An empty final block with fixed codes ends the stream
+    output.write_all(&[3, 0])?;
+    output.write_all(&crc.finalize().to_le_bytes())?;
+    output.write_all(&(size as u32).to_le_bytes())?;
+    output.flush()
+}
+
+/// Opens a decoder over `input`, along with the gzip header of the first
+/// member so that -N can restore the name and time it records.
+fn decoder<'a, R: Read + 'a>(
+    format: Format,
+    input: R,
+) -> io::Result<(Box<dyn Read + 'a>, Option<Header>)> {
+    Ok(match format {
+        Format::Gzip => {
+            let members = Members::new(input)?;
+            let header = members.header.clone();
+            (Box::new(members), Some(header))
+        }
+        Format::Xz => (
+            Box::new(LzmaReader::new_decompressor(input).map_err(io::Error::other)?),
+            None,
+        ),
+        Format::Bzip2 => (Box::new(Bunzip2::new(input)), None),
+    })
+}
+
+fn decompress<W: Write>(decoder: &mut dyn Read, output: &mut W) -> io::Result<()> {
+    io::copy(decoder, output)?;
+    output.flush()
+}
+
+fn ratio(compressed: u64, uncompressed: u64) -> f64 {
+    if uncompressed == 0 {
+        0.0
+    } else {
+        100.0 * (1.0 - compressed as f64 / uncompressed as f64)
+    }
+}
+
+/// Builds the header for a compressed file, recording its name and
+/// modification time unless -n was given.
+fn file_header(path: &Path, metadata: Option<&fs::Metadata>, save_name: bool) -> Header {
+    let mut builder = HeaderBuilder::new();
+    builder.modification_time(0);
+    if let (true, Some(metadata)) = (save_name, metadata) {
+        let mtime = FileTime::from_last_modification_time(metadata).unix_seconds();
+        builder.modification_time(cmp::max(mtime, 0) as u32);
+        if let Some(name) = path.file_name() {
+            if let Ok(name) = CString::new(name.as_bytes()) {
+                builder.filename(name);
+            }
+        }
+    }
+    builder.finish()
+}
+
+/// Returns the name a compressed file decompresses to, or None if it does
+/// not end in the -S suffix or one of the usual suffixes of the format.
+fn decompressed_name(path: &str, opts: &Options) -> Option<String> {
+    let suffix = [(opts.suffix.as_str(), "")];
+    for &(known, replacement) in suffix.iter().chain(opts.format.suffixes()) {
+        if path.len() > known.len() && path.ends_with(known) {
+            return Some(format!(
+                "{}{}",
+                &path[..path.len() - known.len()],
+                replacement
+            ));
+        }
+    }
+    None
+}
+
+/// Checks that an output file may be written, warning about an existing one
+/// unless -f was given.
+fn may_overwrite(path: &Path, opts: &Options) -> bool {
+    if opts.force || fs::symlink_metadata(path).is_err() {
+        return true;
+    }
+    if !opts.quiet {
+        eprintln!(
+            "{}: {} already exists; not overwritten",
+            opts.format.name(),
+            path.display()
+        );
+    }
+    false
+}
+
+/// Processes one operand, returning false if it was skipped with a warning.
+fn process_file(path: &str, opts: &Options) -> io::Result<bool> {
+    let mut stderr = io::stderr();
+    let save_name = opts.name.unwrap_or(opts.mode == Mode::Compress);
+
+    if path == "-" {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        match opts.mode {
+            Mode::Compress => {
+                let header = file_header(Path::new(path), None, save_name);
+                compress(&mut stdin.lock(), stdout.lock(), opts, header)?
+            }
+            Mode::Decompress => {
+                let (mut decoder, _) = decoder(opts.format, stdin.lock())?;
+                decompress(&mut decoder, &mut stdout.lock())?
+            }
+        }
+        return Ok(true);
+    }
+
+    let mut output_path = match opts.mode {
+        Mode::Compress => {
+            if decompressed_name(path, opts).is_some() && !opts.force && !opts.to_stdout {
+                if !opts.quiet {
+                    eprintln!(
+                        "{}: {} already has a compressed suffix -- unchanged",
+                        opts.format.name(),
+                        path
+                    );
+                }
+                return Ok(false);
+            }
+            PathBuf::from(format!("{}{}", path, opts.suffix))
+        }
+        Mode::Decompress => {
+            if let Some(name) = decompressed_name(path, opts) {
+                PathBuf::from(name)
+            } else if opts.to_stdout {
+                PathBuf::new()
+            } else {
+                if !opts.quiet {
+                    eprintln!(
+                        "{}: {}: unknown suffix -- ignored",
+                        opts.format.name(),
+                        path
+                    );
+                }
+                return Ok(false);
+            }
+        }
+    };
+
+    let metadata = fs::metadata(path)?;
+    let mut atime = FileTime::from_last_access_time(&metadata);
+    let mut mtime = FileTime::from_last_modification_time(&metadata);
+
+    let mut input = Counter::new(fs::File::open(path)?);
+    let mut temp = None;
+    let (compressed, uncompressed) = match opts.mode {
+        Mode::Compress => {
+            let header = file_header(Path::new(path), Some(&metadata), save_name);
+            let count = if opts.to_stdout {
+                let stdout = io::stdout();
+                let mut output = Counter::new(stdout.lock());
+                compress(&mut input, &mut output, opts, header)?;
+                output.count
+            } else {
+                if !may_overwrite(&output_path, opts) {
+                    return Ok(false);
+                }
+                let mut output = Counter::new(TempFile::create(&output_path, 0o600)?);
+                compress(&mut input, &mut output, opts, header)?;
+                temp = Some(output.inner);
+                output.count
+            };
+            (count, input.count)
+        }
+        Mode::Decompress => {
+            let count = {
+                let (mut decoder, header) = decoder(opts.format, &mut input)?;
+                if let (true, Some(header)) = (save_name, header) {
+                    // Only the final component of the stored name is used so
+                    // that a crafted header cannot write outside the directory
+                    let name = header
+                        .filename()
+                        .and_then(|name| Path::new(OsStr::from_bytes(name.as_bytes())).file_name());
+                    if let (Some(name), false) = (name, opts.to_stdout) {
+                        output_path = Path::new(path).with_file_name(name);
+                    }
+                    if header.modification_time() != 0 {
+                        mtime = FileTime::from_unix_time(header.modification_time() as i64, 0);
+                        atime = mtime;
+                    }
+                }
+                if opts.to_stdout {
+                    let stdout = io::stdout();
+                    let mut output = Counter::new(stdout.lock());
+                    decompress(&mut decoder, &mut output)?;
+                    output.count
+                } else {
+                    if !may_overwrite(&output_path, opts) {
+                        return Ok(false);
+                    }
+                    let mut output = Counter::new(TempFile::create(&output_path, 0o600)?);
+                    decompress(&mut decoder, &mut output)?;
+                    temp = Some(output.inner);
+                    output.count
+                }
+            };
+            (input.count, count)
+        }
+    };
+
+    if let Some(temp) = temp {
+        temp.file.set_permissions(metadata.permissions())?;
+        filetime::set_file_times(temp.path(), atime, mtime)?;
+        temp.persist(&output_path)?;
+        if !opts.keep {
+            fs::remove_file(path)?;
+        }
+    }
+
+    if opts.verbose {
+        let _ = write!(
+            stderr,
+            "{}:\t{:5.1}%",
+            path,
+            ratio(compressed, uncompressed)
+        );
+        if opts.to_stdout {
+            let _ = writeln!(stderr);
+        } else if opts.keep {
+            let _ = writeln!(stderr, " -- created {}", output_path.display());
+        } else {
+            let _ = writeln!(stderr, " -- replaced with {}", output_path.display());
+        }
+    }
+
+    Ok(true)
+}
+
+fn parse_threads(value: Option<String>, opts: &Options) -> usize {
+    match value.as_ref().and_then(|value| value.parse().ok()) {
+        Some(threads) if threads > 0 => threads,
+        _ => {
+            eprintln!(
+                "{}: invalid number of processes: {}",
+                opts.format.name(),
+                value.unwrap_or_default()
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args();
+    let name = args
+        .next()
+        .as_ref()
+        .and_then(|arg| Path::new(arg).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let (format, mode, to_stdout) = match name.as_str() {
+        "gunzip" => (Format::Gzip, Mode::Decompress, false),
+        "zcat" => (Format::Gzip, Mode::Decompress, true),
+        "xz" => (Format::Xz, Mode::Compress, false),
+        "unxz" => (Format::Xz, Mode::Decompress, false),
+        "xzcat" => (Format::Xz, Mode::Decompress, true),
+        "bzip2" => (Format::Bzip2, Mode::Compress, false),
+        "bunzip2" => (Format::Bzip2, Mode::Decompress, false),
+        "bzcat" => (Format::Bzip2, Mode::Decompress, true),
+        _ => (Format::Gzip, Mode::Compress, false),
+    };
+    let mut opts = Options {
+        format,
+        mode,
+        level: format.default_level(),
+        keep: false,
+        force: false,
+        to_stdout,
+        verbose: false,
+        quiet: false,
+        name: None,
+        test: false,
+        list: false,
+        suffix: format.suffixes()[0].0.to_string(),
+        threads: 1,
+    };
+
+    let mut files = Vec::new();
+    let mut options_done = false;
+    while let Some(arg) = args.next() {
+        if options_done || arg == "-" || !arg.starts_with('-') {
+            files.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "--" => options_done = true,
+            "--decompress" | "--uncompress" => opts.mode = Mode::Decompress,
+            "--stdout" | "--to-stdout" => opts.to_stdout = true,
+            "--keep" => opts.keep = true,
+            "--force" => opts.force = true,
+            "--verbose" => opts.verbose = true,
+            "--quiet" => opts.quiet = true,
+            "--test" => opts.test = true,
+            "--list" => opts.list = true,
+            "--name" => opts.name = Some(true),
+            "--no-name" => opts.name = Some(false),
+            "--suffix" => opts.suffix = args.next().unwrap_or_default(),
+            _ if arg.starts_with("--suffix=") => opts.suffix = arg[9..].to_string(),
+            // Only deflate is compressed in parallel, so -p is rejected for the other formats
+            "--processes" if format == Format::Gzip => {
+                opts.threads = parse_threads(args.next(), &opts)
+            }
+            _ if format == Format::Gzip && arg.starts_with("--processes=") => {
+                opts.threads = parse_threads(Some(arg[12..].to_string()), &opts)
+            }
+            "--fast" => opts.level = 1,
+            "--best" => opts.level = 9,
+            _ if arg.starts_with("--") => {
+                eprintln!("{}: unrecognized option '{}'", opts.format.name(), arg);
+                process::exit(1);
+            }
+            _ => {
+                for (i, c) in arg.char_indices().skip(1) {
+                    match c {
+                        'S' => {
+                            // The suffix is the rest of this argument or the next one
+                            opts.suffix = if i + 1 < arg.len() {
+                                arg[i + 1..].to_string()
+                            } else {
+                                args.next().unwrap_or_default()
+                            };
+                            break;
+                        }
+                        'p' if format == Format::Gzip => {
+                            let value = if i + 1 < arg.len() {
+                                Some(arg[i + 1..].to_string())
+                            } else {
+                                args.next()
+                            };
+                            opts.threads = parse_threads(value, &opts);
+                            break;
+                        }
+                        'd' => opts.mode = Mode::Decompress,
+                        'c' => opts.to_stdout = true,
+                        'k' => opts.keep = true,
+                        'f' => opts.force = true,
+                        'v' => opts.verbose = true,
+                        'q' => opts.quiet = true,
+                        't' => opts.test = true,
+                        'l' => opts.list = true,
+                        'N' => opts.name = Some(true),
+                        'n' => opts.name = Some(false),
+                        '1'..='9' => opts.level = c as u8 - b'0',
+                        _ => {
+                            eprintln!("{}: invalid option -- '{}'", opts.format.name(), c);
+                            process::exit(1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if opts.quiet {
+        opts.verbose = false;
+    }
+    if opts.suffix.is_empty() {
+        eprintln!("{}: invalid suffix ''", opts.format.name());
+        process::exit(1);
+    }
+
+    // With no files, act as a filter from standard input to standard output
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    if opts.test || opts.list {
+        opts.mode = Mode::Decompress;
+    }
+
+    if opts.mode == Mode::Compress
+        && !opts.force
+        && (opts.to_stdout || files.iter().any(|file| file == "-"))
+        && termion::is_tty(&io::stdout())
+    {
+        eprintln!(
+            "{}: compressed data not written to a terminal. Use -f to force compression.",
+            opts.format.name()
+        );
+        process::exit(1);
+    }
+
+    if opts.list {
+        println!("         compressed        uncompressed  ratio uncompressed_name");
+    }
+
+    let mut warned = false;
+    let mut failed = false;
+    let mut totals = (0, 0, 0);
+    for file in files {
+        if opts.test || opts.list {
+            match open_input(&file).and_then(|input| scan(opts.format, input)) {
+                Ok(summary) => {
+                    if opts.list {
+                        let name = match summary.name {
+                            Some(ref name) if opts.name == Some(true) => {
+                                name.to_string_lossy().into_owned()
+                            }
+                            _ => decompressed_name(&file, &opts).unwrap_or(file.clone()),
+                        };
+                        println!(
+                            "{:>19} {:>19} {:5.1}% {}",
+                            summary.compressed,
+                            summary.uncompressed,
+                            ratio(summary.compressed, summary.uncompressed),
+                            name
+                        );
+                        totals.0 += summary.compressed;
+                        totals.1 += summary.uncompressed;
+                        totals.2 += 1;
+                    } else if opts.verbose {
+                        eprintln!("{}:\t OK", file);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{}: {}: {}", opts.format.name(), file, err);
+                    failed = true;
+                }
+            }
+        } else {
+            match process_file(&file, &opts) {
+                Ok(true) => (),
+                Ok(false) => warned = true,
+                Err(err) => {
+                    eprintln!("{}: {}: {}", opts.format.name(), file, err);
+                    failed = true;
+                }
+            }
+        }
+    }
+
+    if opts.list && totals.2 > 1 {
+        println!(
+            "{:>19} {:>19} {:5.1}% (totals)",
+            totals.0,
+            totals.1,
+            ratio(totals.0, totals.1)
+        );
+    }
+    if failed {
+        process::exit(1);
+    }
+    if warned {
+        process::exit(2);
     }
 }
@@ -1,251 +1,1536 @@
 extern crate bzip2;
+extern crate extrautils;
 extern crate filetime;
 extern crate libflate;
+extern crate lz4_flex;
 extern crate lzma;
 extern crate tar;
+extern crate termion;
+extern crate zstd;
 
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{copy, stdin, stdout, BufReader, Error, ErrorKind, Read, Result, Seek, Write};
-use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::io::{
+    self, copy, stdin, stdout, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write,
+};
+use std::os::unix::fs::{symlink, MetadataExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdin, ChildStdout, Command, Stdio};
 use std::str::FromStr;
-use std::{env, process};
+use std::{cmp, env};
 
 use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+use bzip2::Compression as BzCompression;
+use extrautils::date::days_from_civil;
+use extrautils::io::read_full;
+use extrautils::wildcard::wildcard_match;
 use filetime::FileTime;
-use libflate::gzip::Decoder as GzipDecoder;
-use lzma::LzmaReader;
-use tar::{Archive, Builder, EntryType};
-
-fn create_inner<T: Write>(input: &str, ar: &mut Builder<T>) -> Result<()> {
-    if fs::metadata(input)?.is_dir() {
-        for entry_result in fs::read_dir(input)? {
-            let entry = entry_result?;
-            if fs::metadata(entry.path())?.is_dir() {
-                create_inner(entry.path().to_str().unwrap(), ar)?;
+use libflate::gzip::{Decoder as GzipDecoder, Encoder as GzipEncoder};
+use lz4_flex::frame::{FrameDecoder as Lz4Decoder, FrameEncoder as Lz4Encoder};
+use lzma::{LzmaReader, LzmaWriter};
+use tar::{Archive, Builder, Entry, EntryType, GnuExtSparseHeader, Header};
+use termion::clear;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00]) {
+            Compression::Xz
+        } else if magic.starts_with(&[0x1F, 0x8B]) {
+            Compression::Gzip
+        } else if magic.starts_with(b"BZ") {
+            Compression::Bzip2
+        } else if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Compression::Zstd
+        } else if magic.starts_with(&[0x04, 0x22, 0x4D, 0x18]) {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Split a --use-compress-program command line into a command
+fn program_command(program: &str) -> Result<Command> {
+    let mut words = program.split_whitespace();
+    let mut command = Command::new(
+        words
+            .next()
+            .ok_or_else(|| Error::other("empty compression program"))?,
+    );
+    command.args(words);
+    Ok(command)
+}
+
+/// Wait for a compression program, failing if it did not exit successfully
+fn wait_program(child: &mut Child) -> Result<()> {
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "compression program failed: {}",
+            status
+        )))
+    }
+}
+
+/// The output of an external decompression program, which is checked for failure at the end
+struct ProgramReader {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for ProgramReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.stdout.read(buf)?;
+        if len == 0 && !buf.is_empty() {
+            wait_program(&mut self.child)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for ProgramReader {
+    /// Reap the program, stopping it first if its output was not read to the end
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The compressing writer an archive is created with
+enum Encoder {
+    None(Box<dyn Write>),
+    Gzip(GzipEncoder<Box<dyn Write>>),
+    Bzip2(BzEncoder<Box<dyn Write>>),
+    Xz(LzmaWriter<Box<dyn Write>>),
+    Zstd(ZstdEncoder<'static, Box<dyn Write>>),
+    Lz4(Lz4Encoder<Box<dyn Write>>),
+    Program(Child, ChildStdin),
+}
+
+impl Encoder {
+    fn new(tar: &Path, opts: &Options) -> Result<Encoder> {
+        if let Some(ref program) = opts.compress_program {
+            let output = if tar == Path::new("-") {
+                Stdio::inherit()
             } else {
-                println!("{}", entry.path().display());
-                ar.append_path(entry.path())?;
+                Stdio::from(File::create(tar)?)
+            };
+            let mut child = program_command(program)?
+                .stdin(Stdio::piped())
+                .stdout(output)
+                .spawn()?;
+            let stdin = child.stdin.take().unwrap();
+            return Ok(Encoder::Program(child, stdin));
+        }
+
+        let output: Box<dyn Write> = if tar == Path::new("-") {
+            Box::new(stdout())
+        } else {
+            Box::new(File::create(tar)?)
+        };
+        Ok(match opts.compression {
+            Compression::None => Encoder::None(output),
+            Compression::Gzip => Encoder::Gzip(GzipEncoder::new(output)?),
+            Compression::Bzip2 => Encoder::Bzip2(BzEncoder::new(output, BzCompression::Default)),
+            Compression::Xz => {
+                Encoder::Xz(LzmaWriter::new_compressor(output, 6).map_err(Error::other)?)
+            }
+            Compression::Zstd => Encoder::Zstd(ZstdEncoder::new(output, 0)?),
+            Compression::Lz4 => Encoder::Lz4(Lz4Encoder::new(output)),
+        })
+    }
+
+    /// Write any trailer and flush the output
+    fn finish(self) -> Result<()> {
+        let mut output = match self {
+            Encoder::None(output) => output,
+            Encoder::Gzip(encoder) => encoder.finish().into_result()?,
+            Encoder::Bzip2(encoder) => encoder.finish()?,
+            Encoder::Xz(encoder) => encoder.finish().map_err(Error::other)?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+            Encoder::Lz4(encoder) => encoder.finish().map_err(Error::other)?,
+            Encoder::Program(mut child, stdin) => {
+                drop(stdin);
+                return wait_program(&mut child);
             }
+        };
+        output.flush()
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match *self {
+            Encoder::None(ref mut output) => output.write(buf),
+            Encoder::Gzip(ref mut encoder) => encoder.write(buf),
+            Encoder::Bzip2(ref mut encoder) => encoder.write(buf),
+            Encoder::Xz(ref mut encoder) => encoder.write(buf),
+            Encoder::Zstd(ref mut encoder) => encoder.write(buf),
+            Encoder::Lz4(ref mut encoder) => encoder.write(buf),
+            Encoder::Program(_, ref mut stdin) => stdin.write(buf),
         }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match *self {
+            Encoder::None(ref mut output) => output.flush(),
+            Encoder::Gzip(ref mut encoder) => encoder.flush(),
+            Encoder::Bzip2(ref mut encoder) => encoder.flush(),
+            Encoder::Xz(ref mut encoder) => encoder.flush(),
+            Encoder::Zstd(ref mut encoder) => encoder.flush(),
+            Encoder::Lz4(ref mut encoder) => encoder.flush(),
+            Encoder::Program(_, ref mut stdin) => stdin.flush(),
+        }
+    }
+}
+
+/// What to do when a member being extracted already exists
+#[derive(Clone, Copy, PartialEq)]
+enum Overwrite {
+    /// Remove the existing file first, so that running binaries can be replaced
+    Unlink,
+    /// Write into existing regular files in place
+    InPlace,
+    /// Fail for every existing file
+    KeepOld,
+    /// Silently skip existing files
+    SkipOld,
+    /// Skip existing files that are at least as new as the member
+    KeepNewer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CheckpointAction {
+    Echo,
+    Dot,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sort {
+    None,
+    Name,
+}
+
+/// An owner or group override, given as `NAME`, `ID` or `NAME:ID`
+struct Owner {
+    name: Option<String>,
+    id: Option<u64>,
+}
+
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Owner, String> {
+        let (name, id) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let id = match id {
+            Some(id) => Some(u64::from_str(id).map_err(|_| format!("invalid id: {}", id))?),
+            None => None,
+        };
+        if id.is_none() {
+            if let Ok(id) = u64::from_str(name) {
+                return Ok(Owner {
+                    name: None,
+                    id: Some(id),
+                });
+            }
+        }
+        Ok(Owner {
+            name: if name.is_empty() {
+                None
+            } else {
+                Some(name.to_string())
+            },
+            id,
+        })
+    }
+}
+
+struct Options {
+    verbose: bool,
+    /// Whether the archive goes to standard output, so verbose listings go to standard error
+    archive_to_stdout: bool,
+    strip: usize,
+    sort: Sort,
+    mtime: Option<u64>,
+    clamp_mtime: bool,
+    owner: Option<Owner>,
+    group: Option<Owner>,
+    numeric_owner: bool,
+    wildcards: bool,
+    to_stdout: bool,
+    overwrite: Overwrite,
+    /// Number of records between checkpoints
+    checkpoint: Option<u64>,
+    checkpoint_action: CheckpointAction,
+    progress: bool,
+    compression: Compression,
+    compress_program: Option<String>,
+    sparse: bool,
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            verbose: false,
+            archive_to_stdout: false,
+            strip: 0,
+            sort: Sort::None,
+            mtime: None,
+            clamp_mtime: false,
+            owner: None,
+            group: None,
+            numeric_owner: false,
+            wildcards: true,
+            to_stdout: false,
+            overwrite: Overwrite::Unlink,
+            checkpoint: None,
+            checkpoint_action: CheckpointAction::Echo,
+            progress: false,
+            compression: Compression::None,
+            compress_program: None,
+            sparse: false,
+        }
+    }
+
+    /// Apply the --mtime, --owner, --group and --numeric-owner overrides to a header
+    fn apply(&self, header: &mut Header) -> Result<()> {
+        if let Some(mtime) = self.mtime {
+            if !self.clamp_mtime || header.mtime()? > mtime {
+                header.set_mtime(mtime);
+            }
+        }
+        if let Some(ref owner) = self.owner {
+            if let Some(id) = owner.id {
+                header.set_uid(id);
+            }
+            if let Some(ref name) = owner.name {
+                header.set_username(name)?;
+            }
+        }
+        if let Some(ref group) = self.group {
+            if let Some(id) = group.id {
+                header.set_gid(id);
+            }
+            if let Some(ref name) = group.name {
+                header.set_groupname(name)?;
+            }
+        }
+        if self.numeric_owner {
+            header.set_username("")?;
+            header.set_groupname("")?;
+        }
+        Ok(())
+    }
+}
+
+/// Size of a record, the unit that checkpoints are counted in
+const RECORD_SIZE: u64 = 20 * 512;
+
+/// Counts the records passing through an archive reader or writer, acting on every checkpoint
+struct Checkpoints<T> {
+    inner: T,
+    every: Option<u64>,
+    action: CheckpointAction,
+    bytes: u64,
+}
+
+impl<T> Checkpoints<T> {
+    fn new(inner: T, opts: &Options) -> Checkpoints<T> {
+        Checkpoints {
+            inner,
+            every: opts.checkpoint,
+            action: opts.checkpoint_action,
+            bytes: 0,
+        }
+    }
+
+    fn count(&mut self, len: usize, verb: &str) {
+        if let Some(every) = self.every {
+            let before = self.bytes / RECORD_SIZE / every;
+            self.bytes += len as u64;
+            let after = self.bytes / RECORD_SIZE / every;
+            for checkpoint in before + 1..=after {
+                match self.action {
+                    CheckpointAction::Echo => {
+                        eprintln!("tar: {} checkpoint {}", verb, checkpoint * every)
+                    }
+                    CheckpointAction::Dot => eprint!("."),
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for Checkpoints<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count(len, "Read");
+        Ok(len)
+    }
+}
+
+impl<W: Write> Write for Checkpoints<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count(len, "Write");
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
     } else {
-        println!("{}", input);
-        ar.append_path(input)?;
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Draws a progress bar on stderr for the bytes read from the (possibly compressed) input
+struct Progress<R> {
+    inner: R,
+    read: u64,
+    total: Option<u64>,
+    /// What was last drawn, in thousandths of the total or MiB read without one
+    drawn: Option<u64>,
+}
+
+impl<R> Progress<R> {
+    fn new(inner: R, total: Option<u64>) -> Progress<R> {
+        Progress {
+            inner,
+            read: 0,
+            total,
+            drawn: None,
+        }
+    }
+
+    fn draw(&mut self) {
+        let step = match self.total {
+            Some(total) if total > 0 => cmp::min(self.read, total) * 1000 / total,
+            _ => self.read >> 20,
+        };
+        if self.drawn == Some(step) {
+            return;
+        }
+        self.drawn = Some(step);
+
+        let mut stderr = io::stderr();
+        let _ = match self.total {
+            Some(total) => {
+                let label = format!(
+                    " {:>3}% {} / {}",
+                    step / 10,
+                    format_size(self.read),
+                    format_size(total)
+                );
+                let columns = termion::terminal_size().map_or(80, |(w, _)| w as usize);
+                let width = columns.saturating_sub(label.len() + 3);
+                let filled = width * step as usize / 1000;
+                write!(
+                    stderr,
+                    "\r{}[{}{}]{}",
+                    clear::CurrentLine,
+                    "=".repeat(filled),
+                    " ".repeat(width - filled),
+                    label
+                )
+            }
+            None => write!(
+                stderr,
+                "\r{}{} read",
+                clear::CurrentLine,
+                format_size(self.read)
+            ),
+        };
+        let _ = stderr.flush();
+    }
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.read += len as u64;
+        self.draw();
+        Ok(len)
+    }
+}
+
+impl<R> Drop for Progress<R> {
+    fn drop(&mut self) {
+        if self.drawn.is_some() {
+            // Show the final count, the bar is only redrawn when it changes
+            self.drawn = None;
+            self.draw();
+            eprintln!();
+        }
+    }
+}
+
+/// Parse a --mtime argument: `@SECONDS`, `YYYY-MM-DD[ HH:MM[:SS]]` in UTC, or the path of a
+/// file whose modification time should be used
+fn parse_date(date: &str) -> Option<u64> {
+    if let Some(seconds) = date.strip_prefix('@') {
+        return u64::from_str(seconds).ok();
+    }
+    if date.starts_with('/') || date.starts_with('.') {
+        return fs::metadata(date)
+            .ok()
+            .map(|meta| FileTime::from_last_modification_time(&meta).unix_seconds() as u64);
+    }
+
+    let date = date.trim_end_matches('Z');
+    let (day, time) = match date.find([' ', 'T']) {
+        Some(i) => (&date[..i], &date[i + 1..]),
+        None => (date, ""),
+    };
+
+    let day = day
+        .split('-')
+        .map(i64::from_str)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .ok()?;
+    let time = if time.is_empty() {
+        vec![0]
+    } else {
+        time.split(':')
+            .map(i64::from_str)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .ok()?
+    };
+    if day.len() != 3 || time.len() > 3 {
+        return None;
+    }
+    let (year, month, day) = (day[0], day[1], day[2]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let (hour, minute, second) = (
+        time[0],
+        *time.get(1).unwrap_or(&0),
+        *time.get(2).unwrap_or(&0),
+    );
+    if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..60).contains(&second) {
+        return None;
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    if seconds < 0 {
+        None
+    } else {
+        Some(seconds as u64)
+    }
+}
+
+/// Archive `input`, recursing into directories. When `archived` is given, files are skipped
+/// unless they are newer than the last archived copy with the same name.
+fn create_inner<T: Write>(
+    input: &Path,
+    ar: &mut Builder<T>,
+    opts: &Options,
+    archived: Option<&HashMap<PathBuf, u64>>,
+) -> Result<()> {
+    let meta = fs::metadata(input)?;
+    if meta.is_dir() {
+        let mut paths = fs::read_dir(input)?
+            .map(|entry_result| entry_result.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>>>()?;
+        if opts.sort == Sort::Name {
+            paths.sort();
+        }
+        for path in paths {
+            create_inner(&path, ar, opts, archived)?;
+        }
+    } else {
+        if let Some(mtime) = archived.and_then(|archived| archived.get(input)) {
+            if meta.mtime() <= *mtime as i64 {
+                return Ok(());
+            }
+        }
+
+        if opts.verbose {
+            if opts.archive_to_stdout {
+                eprintln!("{}", input.display());
+            } else {
+                println!("{}", input.display());
+            }
+        }
+
+        let mut header = Header::new_gnu();
+        header.set_metadata(&meta);
+        if !meta.is_file() {
+            let rdev = meta.rdev();
+            header.set_size(0);
+            header
+                .set_device_major((((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0xfff)) as u32)?;
+            header.set_device_minor((((rdev >> 12) & 0xffff_ff00) | (rdev & 0xff)) as u32)?;
+        }
+        opts.apply(&mut header)?;
+
+        if meta.is_file() {
+            let mut file = File::open(input)?;
+            let regions = if opts.sparse {
+                sparse_regions(&mut file, meta.len())?
+            } else {
+                None
+            };
+            match regions {
+                Some(regions) => {
+                    let ext_headers = sparse_header(&mut header, &regions, meta.len());
+                    let data = SparseReader {
+                        file,
+                        regions,
+                        index: 0,
+                        left: 0,
+                    };
+                    ar.append_data(&mut header, input, io::Cursor::new(ext_headers).chain(data))?;
+                }
+                None => ar.append_data(&mut header, input, file)?,
+            }
+        } else {
+            ar.append_data(&mut header, input, io::empty())?;
+        }
     }
 
     Ok(())
 }
 
-fn create(input: &str, tar: &str) -> Result<()> {
-    if tar == "-" {
-        create_inner(input, &mut Builder::new(stdout()))
+/// Find the regions of a file that hold data, as offset and length, by scanning for blocks of
+/// zeroes. Returns `None` if the file has no holes.
+fn sparse_regions(file: &mut File, len: u64) -> Result<Option<Vec<(u64, u64)>>> {
+    let mut regions: Vec<(u64, u64)> = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut offset = 0;
+    loop {
+        let read = read_full(file, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        for block in buf[..read].chunks(512) {
+            if block.iter().any(|&byte| byte != 0) {
+                match regions.last_mut() {
+                    Some(last) if last.0 + last.1 == offset => last.1 += block.len() as u64,
+                    _ => regions.push((offset, block.len() as u64)),
+                }
+            }
+            offset += block.len() as u64;
+        }
+    }
+    file.rewind()?;
+
+    if regions.iter().map(|region| region.1).sum::<u64>() == len {
+        return Ok(None);
+    }
+    // A trailing hole is marked by an empty region at the end of the file
+    if !matches!(regions.last(), Some(&(offset, length)) if offset + length == len) {
+        regions.push((len, 0));
+    }
+    Ok(Some(regions))
+}
+
+/// Turn a header into an old GNU sparse header for the given regions, returning the extended
+/// sparse headers that have to precede the data
+fn sparse_header(header: &mut Header, regions: &[(u64, u64)], len: u64) -> Vec<u8> {
+    header.set_entry_type(EntryType::GNUSparse);
+    header.set_size(regions.iter().map(|region| region.1).sum());
+
+    let gnu = header.as_gnu_mut().unwrap();
+    gnu.set_real_size(len);
+    for (sparse, &(offset, length)) in gnu.sparse.iter_mut().zip(regions) {
+        sparse.set_offset(offset);
+        sparse.set_length(length);
+    }
+    gnu.set_is_extended(regions.len() > gnu.sparse.len());
+
+    let mut ext_headers = Vec::new();
+    let mut rest = regions.iter().skip(gnu.sparse.len()).peekable();
+    while rest.peek().is_some() {
+        let mut ext = GnuExtSparseHeader::new();
+        for sparse in ext.sparse.iter_mut() {
+            match rest.next() {
+                Some(&(offset, length)) => {
+                    sparse.set_offset(offset);
+                    sparse.set_length(length);
+                }
+                None => break,
+            }
+        }
+        ext.set_is_extended(rest.peek().is_some());
+        ext_headers.extend_from_slice(ext.as_bytes());
+    }
+    ext_headers
+}
+
+/// Reads the data regions of a sparse file one after another
+struct SparseReader {
+    file: File,
+    regions: Vec<(u64, u64)>,
+    index: usize,
+    left: u64,
+}
+
+impl Read for SparseReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.left == 0 {
+            match self.regions.get(self.index) {
+                Some(&(offset, length)) => {
+                    self.file.seek(SeekFrom::Start(offset))?;
+                    self.left = length;
+                    self.index += 1;
+                }
+                None => return Ok(0),
+            }
+        }
+        let max = cmp::min(buf.len() as u64, self.left) as usize;
+        let len = self.file.read(&mut buf[..max])?;
+        if len == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "file shrank while reading",
+            ));
+        }
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+/// Copy a file, seeking over blocks of zeroes instead of writing them so that holes are recreated
+fn copy_sparse<R: Read>(reader: &mut R, file: &mut File) -> Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let read = read_full(reader, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        for block in buf[..read].chunks(512) {
+            if block.iter().all(|&byte| byte == 0) {
+                file.seek(SeekFrom::Current(block.len() as i64))?;
+            } else {
+                file.write_all(block)?;
+            }
+        }
+        len += read as u64;
+    }
+    file.set_len(len)
+}
+
+fn create(inputs: &[String], tar: &Path, opts: &Options) -> Result<()> {
+    let mut ar = Builder::new(Checkpoints::new(Encoder::new(tar, opts)?, opts));
+    for input in inputs {
+        create_inner(Path::new(input), &mut ar, opts, None)?;
+    }
+    ar.into_inner()?.inner.finish()
+}
+
+/// A member of an uncompressed archive on disk
+struct Member {
+    path: PathBuf,
+    mtime: u64,
+    /// Offset just past the last block of this member. The member starts at the end of the
+    /// previous one, which includes any long name or PAX headers that belong to it.
+    end: u64,
+}
+
+/// Open an archive for in-place modification, refusing compressed archives
+fn open_uncompressed(tar: &Path, create: bool) -> Result<File> {
+    if tar == Path::new("-") {
+        return Err(Error::other("cannot modify an archive on standard input"));
+    }
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(create)
+        .open(tar)?;
+    let mut magic = [0; 6];
+    let len = file.read(&mut magic)?;
+    if Compression::detect(&magic[..len]) != Compression::None {
+        return Err(Error::other("cannot modify a compressed archive"));
+    }
+    Ok(file)
+}
+
+fn scan_members(file: &mut File) -> Result<Vec<Member>> {
+    file.rewind()?;
+    let mut members = Vec::new();
+    let mut extended = Vec::new();
+    for entry_result in Archive::new(&*file).entries()? {
+        let entry = entry_result?;
+        let header = entry.header();
+        let size = header.entry_size()?;
+        if header.entry_type().is_gnu_sparse()
+            && header.as_gnu().is_some_and(|gnu| gnu.is_extended())
+        {
+            extended.push((members.len(), entry.raw_header_position()));
+        }
+        members.push(Member {
+            path: entry.path()?.into_owned(),
+            mtime: header.mtime()?,
+            end: entry.raw_header_position() + 512 + size.div_ceil(512) * 512,
+        });
+    }
+
+    // Old GNU sparse headers are followed by extension headers as long as the previous one has
+    // its extended flag set, these come before the data
+    let mut ext = GnuExtSparseHeader::new();
+    for (index, mut pos) in extended {
+        loop {
+            pos += 512;
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(ext.as_mut_bytes())?;
+            members[index].end += 512;
+            if !ext.is_extended() {
+                break;
+            }
+        }
+    }
+    Ok(members)
+}
+
+/// The member operands selecting which entries an operation applies to
+struct Selection {
+    operands: Vec<String>,
+    wildcards: bool,
+    found: Vec<bool>,
+}
+
+impl Selection {
+    fn new(operands: &[String], wildcards: bool) -> Selection {
+        Selection {
+            operands: operands
+                .iter()
+                .map(|operand| operand.trim_end_matches('/').to_string())
+                .collect(),
+            wildcards,
+            found: vec![false; operands.len()],
+        }
+    }
+
+    /// Whether a member is selected by name, or as being inside a selected directory. Without
+    /// operands every member is selected.
+    fn matches(&mut self, path: &Path) -> bool {
+        if self.operands.is_empty() {
+            return true;
+        }
+
+        let name = path.to_string_lossy();
+        let name = name.trim_end_matches('/').chars().collect::<Vec<_>>();
+        let mut matched = false;
+        for (operand, found) in self.operands.iter().zip(self.found.iter_mut()) {
+            let is_match = if self.wildcards {
+                let pattern = operand.chars().collect::<Vec<_>>();
+                (1..=name.len())
+                    .filter(|&i| i == name.len() || name[i] == '/')
+                    .any(|i| wildcard_match(&pattern, &name[..i]))
+            } else {
+                path.starts_with(operand)
+            };
+            if is_match {
+                *found = true;
+                matched = true;
+            }
+        }
+        matched
+    }
+
+    /// Fail if any operand did not match a member
+    fn finish(self) -> Result<()> {
+        let missing = self
+            .operands
+            .iter()
+            .zip(self.found)
+            .filter(|&(_, found)| !found)
+            .map(|(operand, _)| operand.as_str())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::other(format!(
+                "not found in archive: {}",
+                missing.join(", ")
+            )))
+        }
+    }
+}
+
+fn append(inputs: &[String], tar: &Path, update: bool, opts: &Options) -> Result<()> {
+    let mut file = open_uncompressed(tar, true)?;
+    let members = scan_members(&mut file)?;
+
+    // Overwrite the trailing zero blocks, Builder::finish writes new ones
+    let end = members.last().map_or(0, |member| member.end);
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(end))?;
+
+    let archived = if update {
+        let mut archived = HashMap::new();
+        for member in members {
+            archived.insert(member.path, member.mtime);
+        }
+        Some(archived)
     } else {
-        create_inner(input, &mut Builder::new(File::create(tar)?))
+        None
+    };
+
+    let mut ar = Builder::new(Checkpoints::new(file, opts));
+    for input in inputs {
+        create_inner(Path::new(input), &mut ar, opts, archived.as_ref())?;
+    }
+    ar.finish()
+}
+
+fn delete(selection: &mut Selection, tar: &Path) -> Result<()> {
+    let mut file = open_uncompressed(tar, false)?;
+    let members = scan_members(&mut file)?;
+
+    let mut buf = vec![0; 64 * 1024];
+    let mut start = 0;
+    let mut write_pos = 0;
+    for member in members {
+        if !selection.matches(&member.path) {
+            // Move the member down over any deleted ones, the write position never passes the read
+            // position so this can be done in place
+            let mut read_pos = start;
+            while write_pos != read_pos && read_pos < member.end {
+                let len = cmp::min(buf.len() as u64, member.end - read_pos) as usize;
+                file.seek(SeekFrom::Start(read_pos))?;
+                file.read_exact(&mut buf[..len])?;
+                file.seek(SeekFrom::Start(write_pos))?;
+                file.write_all(&buf[..len])?;
+                read_pos += len as u64;
+                write_pos += len as u64;
+            }
+            write_pos += member.end - read_pos;
+        }
+        start = member.end;
     }
+
+    file.set_len(write_pos)?;
+    file.seek(SeekFrom::Start(write_pos))?;
+    file.write_all(&[0; 1024])
 }
 
-fn list_inner<T: Read>(ar: &mut Archive<T>) -> Result<()> {
+fn list_inner<T: Read>(ar: &mut Archive<T>, selection: &mut Selection) -> Result<()> {
     for entry_result in ar.entries()? {
         let entry = entry_result?;
         let path = entry.path()?;
-        println!("{}", path.display());
+        if selection.matches(&path) {
+            println!("{}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn list(tar: &Path, opts: &Options, selection: &mut Selection) -> Result<()> {
+    list_inner(&mut open_archive(tar, opts)?, selection)
+}
+
+/// Open an archive for reading, decompressing it if necessary
+fn open_archive(tar: &Path, opts: &Options) -> Result<Archive<Box<dyn Read>>> {
+    if let Some(ref program) = opts.compress_program {
+        let input = if tar == Path::new("-") {
+            Stdio::inherit()
+        } else {
+            Stdio::from(File::open(tar)?)
+        };
+        let mut child = program_command(program)?
+            .arg("-d")
+            .stdin(input)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let reader = ProgramReader { child, stdout };
+        return Ok(Archive::new(Box::new(Checkpoints::new(reader, opts))));
+    }
+
+    if tar == Path::new("-") {
+        let input: Box<dyn Read> = if opts.progress {
+            Box::new(Progress::new(stdin(), None))
+        } else {
+            Box::new(stdin())
+        };
+        return Ok(Archive::new(Box::new(Checkpoints::new(input, opts))));
+    }
+
+    let mut file = BufReader::new(File::open(tar)?);
+    let mut magic = [0; 6];
+    let len = read_full(&mut file, &mut magic)?;
+    file.rewind()?;
+    let input: Box<dyn Read> = if opts.progress {
+        let total = file.get_ref().metadata()?.len();
+        Box::new(Progress::new(file, Some(total)))
+    } else {
+        Box::new(file)
+    };
+    let reader: Box<dyn Read> = match Compression::detect(&magic[..len]) {
+        Compression::Xz => Box::new(LzmaReader::new_decompressor(input).map_err(Error::other)?),
+        Compression::Gzip => Box::new(GzipDecoder::new(input).map_err(Error::other)?),
+        Compression::Bzip2 => Box::new(BzDecoder::new(input)),
+        Compression::Zstd => Box::new(ZstdDecoder::new(input)?),
+        Compression::Lz4 => Box::new(Lz4Decoder::new(input)),
+        Compression::None => input,
+    };
+    Ok(Archive::new(Box::new(Checkpoints::new(reader, opts))))
+}
+
+/// The path an entry is extracted to or compared with, or `None` if it was stripped entirely
+fn strip_path(path: &Path, strip: usize) -> Option<PathBuf> {
+    let mut components = path.components();
+    for _ in 0..strip {
+        components.next();
+    }
+    let path = components.as_path();
+    if path == Path::new("") {
+        None
+    } else {
+        Some(path.to_path_buf())
+    }
+}
+
+/// Make way for extracting a member to `path` according to the overwrite policy. Returns
+/// whether the member should be extracted.
+fn prepare_path(path: &Path, header: &Header, opts: &Options) -> Result<bool> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err),
+    };
+
+    let entry_type = header.entry_type();
+    if meta.is_dir() && entry_type == EntryType::Directory {
+        return Ok(true);
+    }
+
+    match opts.overwrite {
+        Overwrite::KeepOld => Err(Error::new(ErrorKind::AlreadyExists, "file exists")),
+        Overwrite::SkipOld => Ok(false),
+        Overwrite::KeepNewer if meta.mtime() >= header.mtime()? as i64 => {
+            eprintln!(
+                "tar: {}: existing file is newer or same age",
+                path.display()
+            );
+            Ok(false)
+        }
+        Overwrite::InPlace if meta.is_file() && entry_type.is_file() => Ok(true),
+        _ => {
+            if meta.is_dir() {
+                fs::remove_dir(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+fn extract_entry<R: Read>(entry: &mut Entry<R>, path: &Path, opts: &Options) -> Result<()> {
+    if !prepare_path(path, entry.header(), opts)? {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match entry.header().entry_type() {
+        EntryType::Regular | EntryType::GNUSparse => {
+            {
+                let mut file = fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .truncate(true)
+                    .create(true)
+                    .mode(entry.header().mode().unwrap_or(0o644))
+                    .open(path)?;
+                if entry.header().entry_type() == EntryType::GNUSparse {
+                    copy_sparse(entry, &mut file)?;
+                } else {
+                    copy(entry, &mut file)?;
+                }
+            }
+            if let Ok(mtime) = entry.header().mtime() {
+                let mtime = FileTime::from_unix_time(mtime as i64, 0);
+                filetime::set_file_times(path, mtime, mtime)?;
+            }
+        }
+        EntryType::Directory => {
+            fs::create_dir_all(path)?;
+        }
+        EntryType::Symlink => {
+            if let Some(target) = entry.link_name()? {
+                symlink(target, path)?;
+            }
+        }
+        other => {
+            return Err(Error::other(format!("unsupported entry type {:?}", other)));
+        }
     }
 
     Ok(())
 }
 
-fn list(tar: &str) -> Result<()> {
-    if tar == "-" {
-        list_inner(&mut Archive::new(stdin()))
+fn extract_inner<T: Read>(
+    ar: &mut Archive<T>,
+    opts: &Options,
+    selection: &mut Selection,
+) -> Result<()> {
+    let mut failed = 0;
+    for entry_result in ar.entries()? {
+        let mut entry = entry_result?;
+        if !selection.matches(&entry.path()?) {
+            continue;
+        }
+
+        let path = match strip_path(&entry.path()?, opts.strip) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        if opts.to_stdout {
+            if entry.header().entry_type().is_file() {
+                copy(&mut entry, &mut stdout())?;
+            }
+            if opts.verbose {
+                eprintln!("{}", entry.path()?.display());
+            }
+            continue;
+        }
+
+        if let Err(err) = extract_entry(&mut entry, &path, opts) {
+            eprintln!("tar: {}: {}", path.display(), err);
+            failed += 1;
+            continue;
+        }
+
+        if opts.verbose {
+            println!("{}", entry.path()?.display());
+        }
+    }
+
+    if failed > 0 {
+        Err(Error::other(format!(
+            "{} member(s) could not be extracted",
+            failed
+        )))
     } else {
-        list_inner(&mut Archive::new(File::open(tar)?))
+        Ok(())
     }
 }
 
-fn create_symlink(link: PathBuf, target: &Path) -> Result<()> {
-    //delete existing file to make way for symlink
-    if link.exists() {
-        fs::remove_file(link.clone())
-            .unwrap_or_else(|e| panic!("could not overwrite: {:?}, {:?}", link, e));
+fn extract(tar: &Path, opts: &Options, selection: &mut Selection) -> Result<()> {
+    extract_inner(&mut open_archive(tar, opts)?, opts, selection)
+}
+
+fn same_contents<A: Read, B: Read>(a: &mut A, b: &mut B) -> Result<bool> {
+    let mut a_buf = [0; 8192];
+    let mut b_buf = [0; 8192];
+    loop {
+        let a_len = read_full(a, &mut a_buf)?;
+        let b_len = read_full(b, &mut b_buf)?;
+        if a_buf[..a_len] != b_buf[..b_len] {
+            return Ok(false);
+        }
+        if a_len == 0 {
+            return Ok(true);
+        }
     }
-    symlink(target, link)
 }
 
-fn extract_inner<T: Read>(ar: &mut Archive<T>, verbose: bool, strip: usize) -> Result<()> {
+/// Compare archive members with the filesystem, reporting each difference. Returns whether any
+/// differences were found.
+fn compare_inner<T: Read>(
+    ar: &mut Archive<T>,
+    opts: &Options,
+    selection: &mut Selection,
+) -> Result<bool> {
+    let mut differs = false;
     for entry_result in ar.entries()? {
         let mut entry = entry_result?;
+        if !selection.matches(&entry.path()?) {
+            continue;
+        }
 
-        let path = {
-            let path = entry.path()?;
-            let mut components = path.components();
-            for _ in 0..strip {
-                components.next();
+        let path = match strip_path(&entry.path()?, opts.strip) {
+            Some(path) => path,
+            None => continue,
+        };
+        if opts.verbose {
+            println!("{}", entry.path()?.display());
+        }
+
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(err) => {
+                println!("{}: Warning: Cannot stat: {}", path.display(), err);
+                differs = true;
+                continue;
             }
-            components.as_path().to_path_buf()
         };
 
-        if path == Path::new("") {
+        let mut report = |difference: &str| {
+            println!("{}: {}", path.display(), difference);
+            differs = true;
+        };
+
+        let header = entry.header().clone();
+        let entry_type = header.entry_type();
+        let type_matches = match entry_type {
+            EntryType::Regular | EntryType::GNUSparse => meta.is_file(),
+            EntryType::Directory => meta.is_dir(),
+            EntryType::Symlink => meta.file_type().is_symlink(),
+            _ => true,
+        };
+        if !type_matches {
+            report("File type differs");
             continue;
         }
 
-        match entry.header().entry_type() {
-            EntryType::Regular => {
-                {
-                    let mut file = {
-                        if let Some(parent) = path.parent() {
-                            fs::create_dir_all(parent)?;
-                        }
-                        fs::OpenOptions::new()
-                            .read(true)
-                            .write(true)
-                            .truncate(true)
-                            .create(true)
-                            .mode(entry.header().mode().unwrap_or(0o644))
-                            .open(&path)?
-                    };
-                    copy(&mut entry, &mut file)?;
+        if entry_type != EntryType::Symlink && header.mode()? & 0o7777 != meta.mode() & 0o7777 {
+            report("Mode differs");
+        }
+
+        match entry_type {
+            EntryType::Regular | EntryType::GNUSparse => {
+                if header.mtime()? as i64 != meta.mtime() {
+                    report("Mod time differs");
                 }
-                if let Ok(mtime) = entry.header().mtime() {
-                    let mtime = FileTime::from_unix_time(mtime as i64, 0);
-                    filetime::set_file_times(&path, mtime, mtime)?;
+                if entry.size() != meta.len() {
+                    report("Size differs");
+                } else {
+                    match File::open(&path) {
+                        Ok(mut file) => {
+                            if !same_contents(&mut entry, &mut file)? {
+                                report("Contents differ");
+                            }
+                        }
+                        Err(err) => report(&format!("Warning: Cannot open: {}", err)),
+                    }
                 }
             }
-            EntryType::Directory => {
-                fs::create_dir_all(&path)?;
-            }
             EntryType::Symlink => {
-                if let Some(target) = entry.link_name().unwrap_or_else(|e| {
-                    panic!("Can't parse symlink target for: {:?}, {:?}", path, e)
-                }) {
-                    create_symlink(path, &target)?
+                let target = entry.link_name()?.map(|target| target.into_owned());
+                match fs::read_link(&path) {
+                    Ok(link) => {
+                        if target != Some(link) {
+                            report("Symlink differs");
+                        }
+                    }
+                    Err(err) => report(&format!("Warning: Cannot readlink: {}", err)),
                 }
             }
-            other => {
-                panic!("Unsupported entry type {:?}", other);
-            }
+            _ => (),
         }
+    }
 
-        if verbose {
-            println!("{}", entry.path()?.display());
+    Ok(differs)
+}
+
+fn compare(tar: &Path, opts: &Options, selection: &mut Selection) -> Result<bool> {
+    compare_inner(&mut open_archive(tar, opts)?, opts, selection)
+}
+
+/// Returns the value of `--name=VALUE` or `--name VALUE` if `arg` is the option `name`
+fn option_value<I: Iterator<Item = String>>(arg: &str, name: &str, args: &mut I) -> Option<String> {
+    if arg == name {
+        match args.next() {
+            Some(value) => Some(value),
+            None => {
+                eprintln!("tar: {} requires an argument", name);
+                process::exit(1);
+            }
         }
+    } else if arg.starts_with(name) && arg[name.len()..].starts_with('=') {
+        Some(arg[name.len() + 1..].to_string())
+    } else {
+        None
     }
-
-    Ok(())
 }
 
-fn extract(tar: &Path, verbose: bool, strip: usize) -> Result<()> {
-    if tar == Path::new("-") {
-        extract_inner(&mut Archive::new(stdin()), verbose, strip)
+/// Resolve an archive operand against the current directory, so that later -C options do not
+/// affect it. `-` stays as is, meaning standard input or output.
+fn archive_path(arg: String) -> PathBuf {
+    if arg == "-" {
+        PathBuf::from(arg)
     } else {
-        let mut file = BufReader::new(File::open(tar)?);
-        let mut magic = [0; 6];
-        file.read_exact(&mut magic)?;
-        file.rewind()?;
-        if magic == [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00] {
-            extract_inner(
-                &mut Archive::new(
-                    LzmaReader::new_decompressor(file)
-                        .map_err(|e| Error::new(ErrorKind::Other, e))?,
-                ),
-                verbose,
-                strip,
-            )
-        } else if magic[..2] == [0x1F, 0x8B] {
-            extract_inner(
-                &mut Archive::new(
-                    GzipDecoder::new(file).map_err(|e| Error::new(ErrorKind::Other, e))?,
-                ),
-                verbose,
-                strip,
-            )
-        } else if &magic[..2] == b"BZ" {
-            extract_inner(&mut Archive::new(BzDecoder::new(file)), verbose, strip)
-        } else {
-            extract_inner(&mut Archive::new(file), verbose, strip)
-        }
+        let mut path = env::current_dir().unwrap();
+        path.push(arg);
+        path
     }
 }
 
 fn main() {
     let mut args = env::args().skip(1);
-    if let Some(op) = args.next() {
-        match op.as_str() {
-            "c" => {
-                if let Some(input) = args.next() {
-                    if let Err(err) = create(&input, "-") {
-                        eprintln!("tar: create: failed: {}", err);
-                        process::exit(1);
-                    }
-                } else {
-                    eprintln!("tar: create: no input specified: {}", op);
+    let op = match args.next() {
+        Some(op) => op,
+        None => {
+            eprintln!("tar: no operation");
+            eprintln!("tar: need to specify cf (create), tf (list), or xf (extract)");
+            process::exit(1);
+        }
+    };
+
+    let mode = if op == "--delete" {
+        Some('D')
+    } else if op == "--compare" || op == "--diff" {
+        Some('d')
+    } else if op.chars().all(|c| "ctxrudfkvOzjJS-".contains(c)) {
+        op.chars().find(|c| "ctxrud".contains(*c))
+    } else {
+        None
+    };
+    let mode = mode.unwrap_or_else(|| {
+        eprintln!("tar: {}: unknown operation\n", op);
+        eprintln!(
+            "tar: need to specify c[f] (create), t[f] (list), x[f] (extract), r[f] (append), \
+             u[f] (update), d[f] (compare), or --delete"
+        );
+        process::exit(1);
+    });
+
+    let mut opts = Options::new();
+    opts.verbose = op.contains('v');
+    opts.to_stdout = op.contains('O');
+    if op.contains('k') {
+        opts.overwrite = Overwrite::KeepOld;
+    }
+    opts.sparse = op.contains('S');
+    if op.contains('z') {
+        opts.compression = Compression::Gzip;
+    } else if op.contains('j') {
+        opts.compression = Compression::Bzip2;
+    } else if op.contains('J') {
+        opts.compression = Compression::Xz;
+    }
+
+    // List, extract and compare always take an archive operand, the other operations only with f
+    let mut tar = None;
+    let takes_tar = (mode != 'D' && op.contains('f')) || "txd".contains(mode);
+    let mut operands = Vec::new();
+    while let Some(arg) = args.next() {
+        let file = if arg == "-f" {
+            args.next().or_else(|| {
+                eprintln!("tar: {} requires path", arg);
+                process::exit(1);
+            })
+        } else {
+            option_value(&arg, "--file", &mut args)
+        };
+
+        if let Some(file) = file {
+            tar = Some(archive_path(file));
+        } else if arg == "-C" {
+            let dir = args.next().unwrap_or_else(|| {
+                eprintln!("tar: {} requires path", arg);
+                process::exit(1);
+            });
+            env::set_current_dir(dir).unwrap();
+        } else if let Some(dir) = option_value(&arg, "--directory", &mut args) {
+            env::set_current_dir(dir).unwrap();
+        } else if let Some(num) = option_value(&arg, "--strip-components", &mut args) {
+            opts.strip = usize::from_str(&num).expect("--strip-components requires an integer");
+        } else if let Some(sort) = option_value(&arg, "--sort", &mut args) {
+            opts.sort = match sort.as_str() {
+                "none" => Sort::None,
+                "name" => Sort::Name,
+                _ => {
+                    eprintln!("tar: --sort: unknown order: {}", sort);
                     process::exit(1);
                 }
-            }
-            "cf" => {
-                if let Some(tar) = args.next() {
-                    if let Some(input) = args.next() {
-                        if let Err(err) = create(&input, &tar) {
-                            eprintln!("tar: create: failed: {}", err);
-                            process::exit(1);
-                        }
-                    } else {
-                        eprintln!("tar: create: no input specified: {}", op);
-                        process::exit(1);
-                    }
-                } else {
+            };
+        } else if let Some(date) = option_value(&arg, "--mtime", &mut args) {
+            opts.mtime = Some(parse_date(&date).unwrap_or_else(|| {
+                eprintln!("tar: --mtime: invalid date: {}", date);
+                process::exit(1);
+            }));
+        } else if arg == "--clamp-mtime" {
+            opts.clamp_mtime = true;
+        } else if let Some(owner) = option_value(&arg, "--owner", &mut args) {
+            opts.owner = Some(owner.parse().unwrap_or_else(|err| {
+                eprintln!("tar: --owner: {}", err);
+                process::exit(1);
+            }));
+        } else if let Some(group) = option_value(&arg, "--group", &mut args) {
+            opts.group = Some(group.parse().unwrap_or_else(|err| {
+                eprintln!("tar: --group: {}", err);
+                process::exit(1);
+            }));
+        } else if arg == "--numeric-owner" {
+            opts.numeric_owner = true;
+        } else if arg == "-O" || arg == "--to-stdout" {
+            opts.to_stdout = true;
+        } else if arg == "-k" || arg == "--keep-old-files" {
+            opts.overwrite = Overwrite::KeepOld;
+        } else if arg == "--skip-old-files" {
+            opts.overwrite = Overwrite::SkipOld;
+        } else if arg == "--keep-newer-files" {
+            opts.overwrite = Overwrite::KeepNewer;
+        } else if arg == "--overwrite" {
+            opts.overwrite = Overwrite::InPlace;
+        } else if arg == "-U" || arg == "--unlink-first" {
+            opts.overwrite = Overwrite::Unlink;
+        } else if arg == "--checkpoint" {
+            opts.checkpoint = Some(10);
+        } else if let Some(num) = arg.strip_prefix("--checkpoint=") {
+            opts.checkpoint = Some(u64::from_str(num).ok().filter(|&n| n > 0).unwrap_or_else(
+                || {
+                    eprintln!("tar: --checkpoint requires a positive integer");
+                    process::exit(1);
+                },
+            ));
+        } else if let Some(action) = option_value(&arg, "--checkpoint-action", &mut args) {
+            opts.checkpoint_action = match action.as_str() {
+                "echo" => CheckpointAction::Echo,
+                "dot" => CheckpointAction::Dot,
+                _ => {
+                    eprintln!("tar: --checkpoint-action: unknown action: {}", action);
+                    process::exit(1);
+                }
+            };
+            opts.checkpoint = opts.checkpoint.or(Some(10));
+        } else if arg == "--progress" {
+            opts.progress = true;
+        } else if arg == "-z" || arg == "--gzip" {
+            opts.compression = Compression::Gzip;
+        } else if arg == "-j" || arg == "--bzip2" {
+            opts.compression = Compression::Bzip2;
+        } else if arg == "-J" || arg == "--xz" {
+            opts.compression = Compression::Xz;
+        } else if arg == "--zstd" {
+            opts.compression = Compression::Zstd;
+        } else if arg == "--lz4" {
+            opts.compression = Compression::Lz4;
+        } else if arg == "-I" {
+            opts.compress_program = Some(args.next().unwrap_or_else(|| {
+                eprintln!("tar: {} requires a program", arg);
+                process::exit(1);
+            }));
+        } else if let Some(program) = option_value(&arg, "--use-compress-program", &mut args) {
+            opts.compress_program = Some(program);
+        } else if arg == "-S" || arg == "--sparse" {
+            opts.sparse = true;
+        } else if arg == "--wildcards" {
+            opts.wildcards = true;
+        } else if arg == "--no-wildcards" {
+            opts.wildcards = false;
+        } else if takes_tar && tar.is_none() && operands.is_empty() {
+            tar = Some(archive_path(arg));
+        } else {
+            operands.push(arg);
+        }
+    }
+
+    match mode {
+        'c' => {
+            let tar = tar.unwrap_or_else(|| {
+                if takes_tar {
                     eprintln!("tar: create: no tarfile specified: {}", op);
                     process::exit(1);
                 }
+                PathBuf::from("-")
+            });
+            if operands.is_empty() {
+                eprintln!("tar: create: no input specified: {}", op);
+                process::exit(1);
             }
-            "t" | "tf" => {
-                let tar = args.next().unwrap_or_else(|| "-".to_string());
-                if let Err(err) = list(&tar) {
-                    eprintln!("tar: list: failed: {}", err);
-                    process::exit(1);
+            opts.archive_to_stdout = tar == Path::new("-");
+            if let Err(err) = create(&operands, &tar, &opts) {
+                eprintln!("tar: create: failed: {}", err);
+                process::exit(1);
+            }
+        }
+        'r' | 'u' | 'D' => {
+            let tar = tar.unwrap_or_else(|| {
+                eprintln!("tar: no tarfile specified: {}", op);
+                process::exit(1);
+            });
+            if opts.compression != Compression::None || opts.compress_program.is_some() {
+                eprintln!("tar: cannot update compressed archives");
+                process::exit(1);
+            }
+            let res = if mode == 'D' {
+                let mut selection = Selection::new(&operands, opts.wildcards);
+                if operands.is_empty() {
+                    Ok(())
+                } else {
+                    delete(&mut selection, &tar).and_then(|()| selection.finish())
                 }
+            } else {
+                append(&operands, &tar, mode == 'u', &opts)
+            };
+            if let Err(err) = res {
+                let name = match mode {
+                    'r' => "append",
+                    'u' => "update",
+                    _ => "delete",
+                };
+                eprintln!("tar: {}: failed: {}", name, err);
+                process::exit(1);
             }
-            "x" | "xf" | "xvf" => {
-                let mut tar = None;
-                let mut strip = 0;
-                while let Some(arg) = args.next() {
-                    if arg == "-C" || arg == "--directory" {
-                        env::set_current_dir(
-                            args.next()
-                                .unwrap_or_else(|| panic!("{} requires path", arg)),
-                        )
-                        .unwrap();
-                    } else if arg.starts_with("--directory=") {
-                        env::set_current_dir(&arg[12..]).unwrap();
-                    } else if arg.starts_with("--strip-components") {
-                        let num = args.next().expect("--strip-components requires an integer");
-                        strip =
-                            usize::from_str(&num).expect("--strip-components requires an integer");
-                    } else if arg.starts_with("--strip-components=") {
-                        strip = usize::from_str(&arg[19..])
-                            .expect("--strip-components requires an integer");
-                    } else if tar.is_none() {
-                        let mut path = env::current_dir().unwrap();
-                        path.push(arg);
-                        tar = Some(path);
+        }
+        'd' => {
+            let tar = tar.unwrap_or_else(|| PathBuf::from("-"));
+            let mut selection = Selection::new(&operands, opts.wildcards);
+            match compare(&tar, &opts, &mut selection) {
+                Ok(differs) => {
+                    if let Err(err) = selection.finish() {
+                        eprintln!("tar: compare: failed: {}", err);
+                        process::exit(2);
+                    }
+                    if differs {
+                        process::exit(1);
                     }
                 }
-                let tar = tar.unwrap_or_else(|| PathBuf::from("-"));
-
-                let verbose = op.contains('v');
-                if let Err(err) = extract(&tar, verbose, strip) {
-                    eprintln!("tar: extract: failed: {}", err);
-                    process::exit(1);
+                Err(err) => {
+                    eprintln!("tar: compare: failed: {}", err);
+                    process::exit(2);
                 }
             }
-            _ => {
-                eprintln!("tar: {}: unknown operation\n", op);
-                eprintln!("tar: need to specify c[f] (create), t[f] (list), or x[f] (extract)");
+        }
+        't' => {
+            let tar = tar.unwrap_or_else(|| PathBuf::from("-"));
+            let mut selection = Selection::new(&operands, opts.wildcards);
+            if let Err(err) = list(&tar, &opts, &mut selection).and_then(|()| selection.finish()) {
+                eprintln!("tar: list: failed: {}", err);
+                process::exit(1);
+            }
+        }
+        _ => {
+            let tar = tar.unwrap_or_else(|| PathBuf::from("-"));
+            let mut selection = Selection::new(&operands, opts.wildcards);
+            if let Err(err) = extract(&tar, &opts, &mut selection).and_then(|()| selection.finish())
+            {
+                eprintln!("tar: extract: failed: {}", err);
                 process::exit(1);
             }
         }
-    } else {
-        eprintln!("tar: no operation");
-        eprintln!("tar: need to specify cf (create), tf (list), or xf (extract)");
-        process::exit(1);
     }
 }
@@ -0,0 +1,2 @@
+// Built from gzip.rs, which picks the format and mode from the program name
+include!("gzip.rs");
@@ -1,68 +1,643 @@
-// From https://raw.githubusercontent.com/mvdnes/zip-rs/master/examples/extract.rs
-
-extern crate zip;
+extern crate extrautils;
+extern crate filetime;
 
+use extrautils::links::{check_links, escape_error};
+use extrautils::wildcard::wildcard_match;
+use extrautils::zip_archive::{Archive, S_IFLNK, S_IFMT};
+use filetime::FileTime;
+use std::ffi::OsStr;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
 
-fn main() {
-    std::process::exit(real_main());
+static MAN_PAGE: &str = /* @MANSTART{unzip} */ r#"
+NAME
+    unzip - list, test and extract files from a zip archive
+
+SYNOPSIS
+    unzip [-lvtpqonh] FILE[.zip] [MEMBER...] [-x MEMBER...] [-d DIR]
+
+DESCRIPTION
+    unzip extracts the members of a zip archive into the current directory, or only the members
+    matching the given MEMBER patterns. Patterns may use the wildcards '*', '?' and '[...]'.
+
+    Extracted files get the Unix mode and modification time recorded in the archive, without
+    set-user-ID, set-group-ID and sticky bits, and symbolic links are recreated. Times are taken from the extended timestamp field when there
+    is one, and otherwise from the MS-DOS time, which is read as UTC.
+
+    Archives larger than 4 GiB or with more than 65535 members (ZIP64) are supported. Names are
+    read as UTF-8 when flagged as such and as code page 437, the MS-DOS character set, otherwise.
+    Encrypted members are skipped.
+
+    Nothing is written outside of the destination directory: absolute names are extracted
+    relative to it with a warning, and members whose names lead outside of it with '..', or
+    through a symbolic link created by an earlier member, are refused. A summary of the members
+    extracted, skipped and failed is printed at the end.
+
+OPTIONS
+    -l
+        List the members of the archive.
+
+    -v
+        List the members verbosely, with their compression method, compressed size and CRC-32.
+
+    -t
+        Test the members by decompressing them and checking their CRC-32.
+
+    -p
+        Extract the members to standard output.
+
+    -d DIR
+        Extract into DIR instead of the current directory.
+
+    -x MEMBER...
+        Exclude the members matching the patterns that follow.
+
+    -o
+        Overwrite existing files without asking.
+
+    -n
+        Never overwrite existing files.
+
+    -q
+        Print fewer messages; -qq prints none.
+
+    -h
+    --help
+        Print this manual page.
+
+EXIT STATUS
+    0 on success, 1 if a warning was printed or an encrypted member was skipped, 2 if some
+    members could not be extracted, 9 if the archive could not be opened, 10 on invalid options,
+    11 if no member matched and 81 if the only members that failed use an unsupported
+    compression method.
+"#; /* @MANEND */
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Extract,
+    List,
+    Test,
+    Pipe,
 }
 
-fn real_main() -> i32 {
-    let args: Vec<_> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: {} <filename>", args[0]);
-        return 1;
-    }
-    let fname = std::path::Path::new(&*args[1]);
-    let file = fs::File::open(&fname).unwrap();
+#[derive(Clone, Copy, PartialEq)]
+enum Overwrite {
+    Ask,
+    Always,
+    Never,
+}
 
-    let mut archive = zip::ZipArchive::new(file).unwrap();
+struct Options {
+    mode: Mode,
+    verbose: bool,
+    overwrite: Overwrite,
+    quiet: u8,
+    directory: PathBuf,
+    includes: Vec<String>,
+    excludes: Vec<String>,
+}
 
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
-        let outpath = file.sanitized_name();
+/// The member patterns selecting which entries are listed, tested or extracted
+struct Selection {
+    includes: Vec<Vec<char>>,
+    excludes: Vec<Vec<char>>,
+    found: Vec<bool>,
+}
+
+impl Selection {
+    fn new(opts: &Options) -> Selection {
+        let chars = |pattern: &String| pattern.chars().collect();
+        Selection {
+            includes: opts.includes.iter().map(chars).collect(),
+            excludes: opts.excludes.iter().map(chars).collect(),
+            found: vec![false; opts.includes.len()],
+        }
+    }
 
+    fn matches(&mut self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        if self
+            .excludes
+            .iter()
+            .any(|pattern| wildcard_match(pattern, &name))
         {
-            let comment = file.comment();
-            if !comment.is_empty() {
-                println!("File {} comment: {}", i, comment);
+            return false;
+        }
+        if self.includes.is_empty() {
+            return true;
+        }
+        let mut matched = false;
+        for (pattern, found) in self.includes.iter().zip(self.found.iter_mut()) {
+            if wildcard_match(pattern, &name) {
+                *found = true;
+                matched = true;
             }
         }
+        matched
+    }
+}
+
+/// The outcome of processing one member, ordered by how much it affects the exit status
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+fn ratio(compressed: u64, size: u64) -> u64 {
+    if size == 0 || compressed >= size {
+        0
+    } else {
+        100 - (compressed * 100 + size / 2) / size
+    }
+}
 
-        if (&*file.name()).ends_with('/') {
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        "file"
+    } else {
+        "files"
+    }
+}
+
+fn list<R>(archive: &Archive<R>, selection: &mut Selection, opts: &Options) {
+    if opts.verbose {
+        println!(" Length   Method    Size  Cmpr    Date    Time   CRC-32   Name");
+        println!("--------  ------  ------- ---- ---------- ----- --------  ----");
+    } else {
+        println!("  Length      Date    Time    Name");
+        println!("---------  ---------- -----   ----");
+    }
+
+    let (mut count, mut size, mut compressed) = (0, 0, 0);
+    for member in &archive.members {
+        if !selection.matches(&member.name) {
+            continue;
+        }
+        if opts.verbose {
             println!(
-                "File {} extracted to \"{}\"",
-                i,
-                outpath.as_path().display()
+                "{:>8}  {:<6} {:>8} {:>3}% {} {:08x}  {}",
+                member.size,
+                member.method_name(),
+                member.compressed_size,
+                ratio(member.compressed_size, member.size),
+                member.format_time(),
+                member.crc,
+                member.name
             );
-            fs::create_dir_all(&outpath).unwrap();
         } else {
             println!(
-                "File {} extracted to \"{}\" ({} bytes)",
-                i,
-                outpath.as_path().display(),
-                file.size()
+                "{:>9}  {}   {}",
+                member.size,
+                member.format_time(),
+                member.name
             );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(&p).unwrap();
+        }
+        count += 1;
+        size += member.size;
+        compressed += member.compressed_size;
+    }
+
+    if opts.verbose {
+        println!("--------          -------  ---                            -------");
+        println!(
+            "{:>8}          {:>7} {:>3}%                            {} {}",
+            size,
+            compressed,
+            ratio(compressed, size),
+            count,
+            plural(count)
+        );
+    } else {
+        println!("---------                     -------");
+        println!(
+            "{:>9}                     {} {}",
+            size,
+            count,
+            plural(count)
+        );
+    }
+}
+
+/// Ask whether to replace an existing file, remembering "all" and "none" answers in `overwrite`
+fn ask_overwrite(path: &Path, overwrite: &mut Overwrite) -> bool {
+    match *overwrite {
+        Overwrite::Always => return true,
+        Overwrite::Never => return false,
+        Overwrite::Ask => (),
+    }
+    let stdin = io::stdin();
+    loop {
+        eprint!("replace {}? [y]es, [n]o, [A]ll, [N]one: ", path.display());
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        match stdin.lock().read_line(&mut answer) {
+            Ok(0) | Err(_) => {
+                // Without anyone to answer, leave existing files alone
+                eprintln!();
+                *overwrite = Overwrite::Never;
+                return false;
+            }
+            Ok(_) => (),
+        }
+        match answer.trim() {
+            "y" | "Y" => return true,
+            "n" => return false,
+            "A" => {
+                *overwrite = Overwrite::Always;
+                return true;
+            }
+            "N" => {
+                *overwrite = Overwrite::Never;
+                return false;
+            }
+            _ => eprintln!("error:  invalid response [{}]", answer.trim()),
+        }
+    }
+}
+
+/// A directory whose mode and time are applied once its contents have been extracted, so that
+/// a read-only directory can still be filled and its time is not changed by adding files
+struct Directory {
+    path: PathBuf,
+    mode: u32,
+    mtime: i64,
+}
+
+/// State kept across the members being extracted
+struct Extraction {
+    overwrite: Overwrite,
+    directories: Vec<Directory>,
+    /// The canonical path of the destination, which nothing may be written outside of
+    root: PathBuf,
+    extracted: usize,
+    skipped: usize,
+}
+
+impl Extraction {
+    /// Apply the modes and times of the extracted directories, innermost first
+    fn finish(&mut self) -> Status {
+        let mut status = Status::Ok;
+        self.directories.sort_by(|a, b| {
+            b.path
+                .components()
+                .count()
+                .cmp(&a.path.components().count())
+        });
+        for dir in self.directories.drain(..) {
+            let mtime = FileTime::from_unix_time(dir.mtime, 0);
+            let permissions = fs::Permissions::from_mode(dir.mode & 0o777);
+            let result = filetime::set_file_times(&dir.path, mtime, mtime)
+                .and_then(|()| fs::set_permissions(&dir.path, permissions));
+            if let Err(err) = result {
+                eprintln!(
+                    "warning:  cannot set mode or time of {}: {}",
+                    dir.path.display(),
+                    err
+                );
+                status = Status::Warning;
+            }
+        }
+        status
+    }
+}
+
+/// Extract or test one member, reporting any problem and returning how it went
+fn process_member<R: Read + Seek>(
+    archive: &mut Archive<R>,
+    index: usize,
+    opts: &Options,
+    extraction: &mut Extraction,
+) -> io::Result<Status> {
+    let member = &archive.members[index];
+    let name = member.name.clone();
+    let is_dir = member.is_dir();
+    let mode = member.unix_mode();
+    let mtime = member.mtime();
+    let method = member.method;
+    let path = member.relative_path();
+
+    match opts.mode {
+        Mode::Test => {
+            if opts.quiet == 0 {
+                print!("    testing: {:<36} ", name);
+                let _ = io::stdout().flush();
+            }
+            io::copy(&mut archive.open(index)?, &mut io::sink())?;
+            if opts.quiet == 0 {
+                println!("OK");
+            }
+            return Ok(Status::Ok);
+        }
+        Mode::Pipe => {
+            if !is_dir {
+                let stdout = io::stdout();
+                io::copy(&mut archive.open(index)?, &mut stdout.lock())?;
+            }
+            return Ok(Status::Ok);
+        }
+        Mode::Extract | Mode::List => (),
+    }
+
+    let (relative, rewritten) = path.ok_or_else(escape_error)?;
+    check_links(&extraction.root, &relative, is_dir)?;
+    let mut status = Status::Ok;
+    if rewritten {
+        eprintln!("warning:  stripped absolute path spec from {}", name);
+        status = Status::Warning;
+    }
+    let outpath = opts.directory.join(relative);
+
+    if is_dir {
+        if opts.quiet == 0 && !outpath.is_dir() {
+            println!("   creating: {}/", outpath.display());
+        }
+        fs::create_dir_all(&outpath)?;
+        extraction.directories.push(Directory {
+            path: outpath,
+            mode,
+            mtime,
+        });
+        extraction.extracted += 1;
+        return Ok(status);
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(&outpath) {
+        if !ask_overwrite(&outpath, &mut extraction.overwrite) {
+            extraction.skipped += 1;
+            return Ok(status);
+        }
+        // Replace rather than write through an existing file, which may be a link
+        if !metadata.is_dir() {
+            fs::remove_file(&outpath)?;
+        }
+    }
+    if let Some(parent) = outpath.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if mode & S_IFMT == S_IFLNK {
+        // The data of a link is its target
+        let mut target = Vec::new();
+        archive.open(index)?.read_to_end(&mut target)?;
+        let target = Path::new(OsStr::from_bytes(&target));
+        if opts.quiet == 0 {
+            println!("    linking: {} -> {}", outpath.display(), target.display());
+        }
+        std::os::unix::fs::symlink(target, &outpath)?;
+        extraction.extracted += 1;
+        return Ok(status);
+    }
+
+    if opts.quiet == 0 {
+        let verb = if method == 8 {
+            "  inflating"
+        } else {
+            " extracting"
+        };
+        println!("{}: {}", verb, outpath.display());
+    }
+    let mut outfile = fs::File::create(&outpath)?;
+    io::copy(&mut archive.open(index)?, &mut outfile)?;
+    drop(outfile);
+    extraction.extracted += 1;
+
+    let mtime = FileTime::from_unix_time(mtime, 0);
+    let result = filetime::set_file_times(&outpath, mtime, mtime)
+        .and_then(|()| fs::set_permissions(&outpath, fs::Permissions::from_mode(mode & 0o777)));
+    if let Err(err) = result {
+        eprintln!(
+            "warning:  cannot set mode or time of {}: {}",
+            outpath.display(),
+            err
+        );
+        return Ok(Status::Warning);
+    }
+    Ok(status)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: unzip [-lvtpqon] FILE[.zip] [MEMBER...] [-x MEMBER...] [-d DIR]");
+    process::exit(10);
+}
+
+fn parse_args() -> (String, Options) {
+    let mut opts = Options {
+        mode: Mode::Extract,
+        verbose: false,
+        overwrite: Overwrite::Ask,
+        quiet: 0,
+        directory: PathBuf::new(),
+        includes: Vec::new(),
+        excludes: Vec::new(),
+    };
+    let mut archive = None;
+    let mut excluding = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            print!("{}", MAN_PAGE);
+            process::exit(0);
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            if archive.is_none() {
+                archive = Some(arg);
+            } else if excluding {
+                opts.excludes.push(arg);
+            } else {
+                opts.includes.push(arg);
+            }
+            continue;
+        }
+        excluding = false;
+        for (i, c) in arg.char_indices().skip(1) {
+            match c {
+                'l' => opts.mode = Mode::List,
+                'v' => {
+                    opts.mode = Mode::List;
+                    opts.verbose = true;
+                }
+                't' => opts.mode = Mode::Test,
+                'p' => opts.mode = Mode::Pipe,
+                'o' => opts.overwrite = Overwrite::Always,
+                'n' => opts.overwrite = Overwrite::Never,
+                'q' => opts.quiet += 1,
+                'x' => excluding = true,
+                'd' => {
+                    // The directory is the rest of this argument or the next one
+                    let dir = if i + 1 < arg.len() {
+                        arg[i + 1..].to_string()
+                    } else {
+                        args.next().unwrap_or_else(|| usage())
+                    };
+                    opts.directory = PathBuf::from(dir);
+                    break;
+                }
+                'h' => {
+                    print!("{}", MAN_PAGE);
+                    process::exit(0);
+                }
+                _ => {
+                    eprintln!("unzip: invalid option -- '{}'", c);
+                    usage();
                 }
             }
-            let mut outfile = fs::File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
         }
+    }
 
-        // Get and Set permissions
-        #[cfg(any(unix, target_os = "redox"))]
-        {
-            use std::os::unix::fs::PermissionsExt;
+    match archive {
+        Some(archive) => (archive, opts),
+        None => usage(),
+    }
+}
+
+fn main() {
+    process::exit(real_main());
+}
+
+fn real_main() -> i32 {
+    let (name, opts) = parse_args();
+
+    // As in Info-ZIP, FILE may be given without its .zip suffix
+    let path = if !Path::new(&name).exists() && Path::new(&format!("{}.zip", name)).exists() {
+        format!("{}.zip", name)
+    } else {
+        name
+    };
+    let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("unzip: cannot find or open {}: {}", path, err);
+            return 9;
+        }
+    };
+    let mut archive = match Archive::new(io::BufReader::new(file)) {
+        Ok(archive) => archive,
+        Err(err) => {
+            eprintln!("unzip: {}: {}", path, err);
+            return 9;
+        }
+    };
 
-            if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).unwrap();
+    if opts.quiet == 0 && opts.mode != Mode::Pipe || opts.mode == Mode::List {
+        println!("Archive:  {}", path);
+    }
+
+    let mut selection = Selection::new(&opts);
+    let mut status = Status::Ok;
+    let mut unsupported = 0;
+    let mut failed = 0;
+
+    if opts.mode == Mode::List {
+        list(&archive, &mut selection, &opts);
+    } else {
+        // Create the destination first, to know where it really is
+        let root = if opts.mode == Mode::Extract {
+            let directory = if opts.directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                opts.directory.as_path()
+            };
+            match fs::create_dir_all(directory).and_then(|()| fs::canonicalize(directory)) {
+                Ok(root) => root,
+                Err(err) => {
+                    eprintln!(
+                        "unzip: cannot create extraction directory {}: {}",
+                        directory.display(),
+                        err
+                    );
+                    return 3;
+                }
+            }
+        } else {
+            PathBuf::new()
+        };
+        let mut extraction = Extraction {
+            overwrite: opts.overwrite,
+            directories: Vec::new(),
+            root,
+            extracted: 0,
+            skipped: 0,
+        };
+        for i in 0..archive.members.len() {
+            let member = &archive.members[i];
+            if !selection.matches(&member.name) {
+                continue;
+            }
+            // Directories have no data to decrypt or decompress
+            if member.encrypted() && !member.is_dir() {
+                eprintln!("   skipping: {:<24} encrypted", member.name);
+                extraction.skipped += 1;
+                if status < Status::Warning {
+                    status = Status::Warning;
+                }
+                continue;
             }
+            if !matches!(member.method, 0 | 8 | 12) && !member.is_dir() {
+                eprintln!(
+                    "   skipping: {:<24} unsupported compression method {}",
+                    member.name, member.method
+                );
+                status = Status::Error;
+                unsupported += 1;
+                failed += 1;
+                continue;
+            }
+            match process_member(&mut archive, i, &opts, &mut extraction) {
+                Ok(member) => {
+                    if member > status {
+                        status = member;
+                    }
+                }
+                Err(err) => {
+                    if opts.mode == Mode::Test && opts.quiet == 0 {
+                        println!("ERROR");
+                    }
+                    eprintln!("unzip: {}: {}", archive.members[i].name, err);
+                    status = Status::Error;
+                    failed += 1;
+                }
+            }
+        }
+        let directories = extraction.finish();
+        if directories > status {
+            status = directories;
         }
+        if opts.mode == Mode::Extract && opts.quiet < 2 {
+            println!(
+                "{} extracted, {} skipped, {} failed",
+                extraction.extracted, extraction.skipped, failed
+            );
+        }
+    }
+
+    let mut unmatched = false;
+    for (pattern, found) in opts.includes.iter().zip(&selection.found) {
+        if !found {
+            eprintln!("caution: filename not matched:  {}", pattern);
+            unmatched = true;
+        }
+    }
+
+    if opts.mode == Mode::Test && opts.quiet < 2 {
+        if failed == 0 {
+            println!("No errors detected in compressed data of {}.", path);
+        } else {
+            println!("At least one error was detected in {}.", path);
+        }
+    }
+
+    match status {
+        Status::Error if unsupported == failed => 81,
+        Status::Error => 2,
+        _ if unmatched => 11,
+        Status::Warning => 1,
+        Status::Ok => 0,
     }
-    0
 }
@@ -0,0 +1,768 @@
+extern crate crc32fast;
+extern crate extrautils;
+extern crate filetime;
+extern crate libflate;
+
+use extrautils::date::civil_from_days;
+use extrautils::deflate;
+use extrautils::temp_file::TempFile;
+use extrautils::wildcard::wildcard_match;
+use extrautils::zip_archive::{
+    extra_field, extra_field_range, invalid, le_u16, le_u32, Archive, Member, EXTRA_TIMESTAMP,
+    EXTRA_ZIP64, FLAG_DESCRIPTOR, FLAG_UTF8,
+};
+use filetime::FileTime;
+use libflate::deflate::{EncodeOptions, Encoder};
+use libflate::lz77::DefaultLz77Encoder;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::{cmp, env, process};
+
+static MAN_PAGE: &str = /* @MANSTART{zip} */ r#"
+NAME
+    zip - package and compress files into a zip archive
+
+SYNOPSIS
+    zip [-rjuq0-9] [-n SUFFIXES] ARCHIVE[.zip] FILE... [-x PATTERN...]
+    zip -d ARCHIVE[.zip] PATTERN... [-x PATTERN...]
+
+DESCRIPTION
+    zip adds the FILEs to ARCHIVE, creating it if it does not exist and replacing members that
+    have the same name. Each member is deflated, or stored as it is when deflating would not make
+    it smaller. The Unix mode and modification time of every file are recorded in the archive.
+    Members that are kept are copied byte for byte, including encrypted ones.
+
+    A '.zip' suffix is appended to ARCHIVE if it has none. The archive is written to a temporary
+    file which replaces it once complete.
+
+OPTIONS
+    -r
+        Add the contents of directories recursively.
+
+    -j
+        Junk paths: store only the file names, without their directories.
+
+    -u
+        Update: only replace members whose file is newer than the member, and add new files.
+
+    -d
+        Delete the members matching the PATTERNs from the archive.
+
+    -x PATTERN...
+        Exclude the members matching the patterns that follow. Patterns may use the wildcards
+        '*', '?' and '[...]'.
+
+    -0
+        Store all members without compression.
+
+    -1 ... -9
+        Compression level, -1 is the fastest and -9 compresses best. The default is -6.
+
+    -n SUFFIXES
+        Store files whose names end in one of the colon-separated SUFFIXES without trying to
+        compress them, e.g. -n .jpg:.png:.gz
+
+    -q
+        Quiet, do not report each member.
+
+    -h
+    --help
+        Print this manual page.
+
+EXIT STATUS
+    0 on success, 3 if the archive is not a valid zip file, 12 if there is nothing to do,
+    14 if writing the archive failed, 16 on invalid options and 18 if a FILE could not be read.
+"#; /* @MANEND */
+
+/// Suffixes which are stored by default as they are usually compressed already
+const STORE_SUFFIXES: &[&str] = &[".Z", ".zip", ".zoo", ".arc", ".lzh", ".arj"];
+
+/// The version needed to extract, 1.0 for stored members and 2.0 for deflate and directories
+const VERSION_STORE: u16 = 10;
+const VERSION_DEFLATE: u16 = 20;
+/// Made by Unix, version 3.0 of the specification
+const VERSION_MADE_BY: u16 = (3 << 8) | 30;
+
+struct Options {
+    recurse: bool,
+    junk_paths: bool,
+    update: bool,
+    delete: bool,
+    level: u32,
+    quiet: bool,
+    store_suffixes: Vec<String>,
+}
+
+fn matches_any(patterns: &[Vec<char>], name: &[u8]) -> bool {
+    let name: Vec<char> = String::from_utf8_lossy(name).chars().collect();
+    patterns
+        .iter()
+        .any(|pattern| wildcard_match(pattern, &name))
+}
+
+/// Pack a date and time into the MS-DOS format, date in the high half, which has a two
+/// second resolution and covers the years 1980 to 2107
+fn dos_time(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> u32 {
+    if year < 1980 {
+        return (1 << 5 | 1) << 16;
+    }
+    let year = cmp::min(year - 1980, 127);
+    let date = (year << 9 | month << 5 | day) as u32;
+    let time = (hour << 11 | minute << 5 | (second / 2)) as u32;
+    date << 16 | time
+}
+
+/// The MS-DOS time of a Unix timestamp. There is no portable way to find the local time zone,
+/// so this is UTC; the exact time is kept in the extended timestamp field.
+fn dos_time_from_unix(seconds: i64) -> u32 {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    dos_time(year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+/// Where the data of a member comes from
+enum Source {
+    /// Copied unchanged from the existing archive
+    Archive(Member),
+    /// Read from a file, or nothing for a directory
+    File(PathBuf),
+}
+
+/// A member of the archive being written
+struct Entry {
+    name: Vec<u8>,
+    source: Source,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    dos_time: u32,
+    /// Unix modification time, stored in an extended timestamp field as a signed value
+    mtime: Option<i32>,
+    external_attributes: u32,
+    /// Offset of the local header in the new archive
+    offset: u64,
+}
+
+impl Entry {
+    fn is_dir(&self) -> bool {
+        self.name.ends_with(b"/")
+    }
+
+    fn flags(&self) -> u16 {
+        if !self.name.is_ascii() && std::str::from_utf8(&self.name).is_ok() {
+            FLAG_UTF8
+        } else {
+            0
+        }
+    }
+
+    fn version_needed(&self) -> u16 {
+        if self.method == 8 || self.is_dir() {
+            VERSION_DEFLATE
+        } else {
+            VERSION_STORE
+        }
+    }
+
+    fn extra_field(&self) -> Vec<u8> {
+        let mut extra = Vec::new();
+        if let Some(mtime) = self.mtime {
+            extra.extend_from_slice(&EXTRA_TIMESTAMP.to_le_bytes());
+            extra.extend_from_slice(&5u16.to_le_bytes());
+            // Only the modification time is present
+            extra.push(1);
+            extra.extend_from_slice(&mtime.to_le_bytes());
+        }
+        extra
+    }
+
+    fn local_header(&self) -> Vec<u8> {
+        let extra = self.extra_field();
+        let mut header = Vec::with_capacity(30 + self.name.len() + extra.len());
+        header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        header.extend_from_slice(&self.version_needed().to_le_bytes());
+        header.extend_from_slice(&self.flags().to_le_bytes());
+        header.extend_from_slice(&self.method.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.to_le_bytes());
+        header.extend_from_slice(&self.crc.to_le_bytes());
+        header.extend_from_slice(&(self.compressed_size as u32).to_le_bytes());
+        header.extend_from_slice(&(self.size as u32).to_le_bytes());
+        header.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(&self.name);
+        header.extend_from_slice(&extra);
+        header
+    }
+
+    fn central_header(&self) -> Vec<u8> {
+        if let Source::Archive(ref member) = self.source {
+            return copied_header(member, self.offset);
+        }
+        let extra = self.extra_field();
+        let mut header = Vec::with_capacity(46 + self.name.len() + extra.len());
+        header.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        header.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        header.extend_from_slice(&self.version_needed().to_le_bytes());
+        header.extend_from_slice(&self.flags().to_le_bytes());
+        header.extend_from_slice(&self.method.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.to_le_bytes());
+        header.extend_from_slice(&self.crc.to_le_bytes());
+        header.extend_from_slice(&(self.compressed_size as u32).to_le_bytes());
+        header.extend_from_slice(&(self.size as u32).to_le_bytes());
+        header.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        // Comment length, disk number and internal attributes
+        header.extend_from_slice(&[0; 6]);
+        header.extend_from_slice(&self.external_attributes.to_le_bytes());
+        header.extend_from_slice(&(self.offset as u32).to_le_bytes());
+        header.extend_from_slice(&self.name);
+        header.extend_from_slice(&extra);
+        header
+    }
+}
+
+/// Computes the CRC-32 and size of the data read through it
+struct Checksum<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    count: u64,
+}
+
+impl<R: Read> Read for Checksum<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+fn checksum<R: Read>(inner: R) -> Checksum<R> {
+    Checksum {
+        inner,
+        hasher: crc32fast::Hasher::new(),
+        count: 0,
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("too large for a zip file without ZIP64 extensions")
+}
+
+/// Write the data of a file member at the current position of `output`, filling in its method,
+/// CRC-32 and sizes
+fn write_data(
+    entry: &mut Entry,
+    path: &Path,
+    output: &mut fs::File,
+    opts: &Options,
+) -> io::Result<()> {
+    let data_start = output.stream_position()?;
+    let name = path.as_os_str().as_bytes();
+    let store = opts.level == 0
+        || opts
+            .store_suffixes
+            .iter()
+            .any(|suffix| name.ends_with(suffix.as_bytes()));
+
+    if !store {
+        let window = deflate::window_size(opts.level);
+        let options = EncodeOptions::with_lz77(DefaultLz77Encoder::with_window_size(window));
+        let mut input = checksum(fs::File::open(path)?);
+        let mut encoder = Encoder::with_options(BufWriter::new(&mut *output), options);
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish().into_result()?.flush()?;
+
+        let compressed_size = output.stream_position()? - data_start;
+        if compressed_size < input.count {
+            entry.method = 8;
+            entry.crc = input.hasher.finalize();
+            entry.size = input.count;
+            entry.compressed_size = compressed_size;
+            return Ok(());
+        }
+        // Deflating did not help, so store the file instead
+        output.seek(SeekFrom::Start(data_start))?;
+        output.set_len(data_start)?;
+    }
+
+    let mut input = checksum(fs::File::open(path)?);
+    io::copy(&mut input, output)?;
+    entry.method = 0;
+    entry.crc = input.hasher.finalize();
+    entry.size = input.count;
+    entry.compressed_size = input.count;
+    Ok(())
+}
+
+/// Copy a member of the existing archive unchanged, its local header, data and data descriptor,
+/// returning the offset it was written at
+fn copy_member(member: &Member, archive: &mut fs::File, output: &mut fs::File) -> io::Result<u64> {
+    let offset = output.stream_position()?;
+    if offset > u64::from(u32::MAX) {
+        return Err(too_large());
+    }
+    archive.seek(SeekFrom::Start(member.header_offset))?;
+    let mut header = [0; 30];
+    archive.read_exact(&mut header)?;
+    if header[..4] != [0x50, 0x4b, 0x03, 0x04] {
+        return Err(invalid("bad local header"));
+    }
+    let name_len = le_u16(&header[26..]) as usize;
+    let mut name_extra = vec![0; name_len + le_u16(&header[28..]) as usize];
+    archive.read_exact(&mut name_extra)?;
+
+    // A data descriptor follows the data when its sizes and CRC-32 were not known in advance. It
+    // may start with a signature, and has 64 bit sizes in ZIP64 members.
+    let mut len = member.compressed_size;
+    if member.flags & FLAG_DESCRIPTOR != 0 {
+        let data_start = archive.stream_position()?;
+        archive.seek(SeekFrom::Current(member.compressed_size as i64))?;
+        let mut signature = [0; 4];
+        archive.read_exact(&mut signature)?;
+        let sizes = if extra_field(&name_extra[name_len..], EXTRA_ZIP64).is_some() {
+            16
+        } else {
+            8
+        };
+        len += 4 + sizes;
+        if signature == [0x50, 0x4b, 0x07, 0x08] {
+            len += 4;
+        }
+        archive.seek(SeekFrom::Start(data_start))?;
+    }
+
+    output.write_all(&header)?;
+    output.write_all(&name_extra)?;
+    let copied = io::copy(&mut archive.take(len), output)?;
+    if copied != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "archive is truncated",
+        ));
+    }
+    Ok(offset)
+}
+
+/// The central directory record of a copied member, with the offset of its local header changed
+/// to where it now is
+fn copied_header(member: &Member, offset: u64) -> Vec<u8> {
+    let mut record = member.record.clone();
+    // An offset too large for its field is in the ZIP64 extra field, after the sizes that are
+    // there as well
+    if le_u32(&record[42..]) == 0xFFFF_FFFF {
+        let extra_start = 46 + le_u16(&record[28..]) as usize;
+        let extra = &record[extra_start..extra_start + le_u16(&record[30..]) as usize];
+        if let Some(field) = extra_field_range(extra, EXTRA_ZIP64) {
+            let sizes = [24, 20]
+                .iter()
+                .filter(|&&pos| le_u32(&record[pos..]) == 0xFFFF_FFFF)
+                .count();
+            let pos = extra_start + field.start + sizes * 8;
+            if pos + 8 <= extra_start + field.end {
+                record[pos..pos + 8].copy_from_slice(&offset.to_le_bytes());
+                return record;
+            }
+        }
+    }
+    record[42..46].copy_from_slice(&(offset as u32).to_le_bytes());
+    record
+}
+
+/// Write a member read from a file, returning a description of how it was compressed
+fn write_entry(entry: &mut Entry, output: &mut fs::File, opts: &Options) -> io::Result<String> {
+    entry.offset = output.stream_position()?;
+    if entry.offset > u64::from(u32::MAX) {
+        return Err(too_large());
+    }
+    // The local header is written again once the sizes are known
+    let header_len = entry.local_header().len() as u64;
+    output.seek(SeekFrom::Current(header_len as i64))?;
+
+    if let Source::File(ref path) = entry.source {
+        if !entry.is_dir() {
+            let path = path.clone();
+            write_data(entry, &path, output, opts)?;
+        }
+    }
+    if entry.compressed_size > u64::from(u32::MAX) || entry.size > u64::from(u32::MAX) {
+        return Err(too_large());
+    }
+
+    let end = output.stream_position()?;
+    output.seek(SeekFrom::Start(entry.offset))?;
+    output.write_all(&entry.local_header())?;
+    output.seek(SeekFrom::Start(end))?;
+
+    Ok(if entry.method == 8 {
+        let saved = entry.size.saturating_sub(entry.compressed_size);
+        let percent = (saved * 100 + entry.size / 2)
+            .checked_div(entry.size)
+            .unwrap_or(0);
+        format!("deflated {}%", percent)
+    } else {
+        "stored 0%".to_string()
+    })
+}
+
+/// Write the central directory and its end record after the members
+fn write_central_directory(entries: &[Entry], output: &mut fs::File) -> io::Result<()> {
+    if entries.len() > usize::from(u16::MAX) {
+        return Err(too_large());
+    }
+    let start = output.stream_position()?;
+    let mut directory = Vec::new();
+    for entry in entries {
+        directory.extend_from_slice(&entry.central_header());
+    }
+    if start + directory.len() as u64 > u64::from(u32::MAX) {
+        return Err(too_large());
+    }
+
+    let size = directory.len() as u32;
+    directory.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    // Number of this disk and of the disk with the central directory
+    directory.extend_from_slice(&[0; 4]);
+    directory.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    directory.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    directory.extend_from_slice(&size.to_le_bytes());
+    directory.extend_from_slice(&(start as u32).to_le_bytes());
+    // Comment length
+    directory.extend_from_slice(&[0; 2]);
+    output.write_all(&directory)
+}
+
+/// The members of an existing archive, to be copied into the new one
+fn read_archive(path: &Path) -> io::Result<Vec<Entry>> {
+    let archive = Archive::new(io::BufReader::new(fs::File::open(path)?))?;
+    Ok(archive
+        .members
+        .into_iter()
+        .map(|member| Entry {
+            name: member.raw_name().to_vec(),
+            method: member.method,
+            crc: member.crc,
+            compressed_size: member.compressed_size,
+            size: member.size,
+            dos_time: member.dos_time,
+            mtime: None,
+            external_attributes: member.external_attributes,
+            offset: 0,
+            source: Source::Archive(member),
+        })
+        .collect())
+}
+
+/// Unix mode in the high half, and the MS-DOS directory and read-only bits in the low byte
+fn external_attributes(mode: u32) -> u32 {
+    let mut attributes = mode << 16;
+    if mode & 0o170000 == 0o040000 {
+        attributes |= 0x10;
+    }
+    if mode & 0o222 == 0 {
+        attributes |= 0x01;
+    }
+    attributes
+}
+
+/// The name a path is stored under: relative, without `.` and `..` components, and with a
+/// trailing `/` for directories
+fn entry_name(path: &Path, is_dir: bool, opts: &Options) -> Vec<u8> {
+    let mut name = Vec::new();
+    if opts.junk_paths {
+        name.extend_from_slice(path.file_name().unwrap_or_default().as_bytes());
+    } else {
+        for component in path.components() {
+            if let Component::Normal(part) = component {
+                if !name.is_empty() {
+                    name.push(b'/');
+                }
+                name.extend_from_slice(part.as_bytes());
+            }
+        }
+    }
+    if is_dir && !name.is_empty() {
+        name.push(b'/');
+    }
+    name
+}
+
+/// Collects the files to add
+struct Inputs<'a> {
+    opts: &'a Options,
+    excludes: &'a [Vec<char>],
+    /// Device and inode of the archive, which must not be added to itself
+    archive: Option<(u64, u64)>,
+    entries: Vec<Entry>,
+    failed: bool,
+}
+
+impl<'a> Inputs<'a> {
+    fn add(&mut self, path: &Path) {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                eprintln!("zip warning: {}: {}", path.display(), err);
+                self.failed = true;
+                return;
+            }
+        };
+        if self.archive == Some((metadata.dev(), metadata.ino())) {
+            return;
+        }
+
+        let is_dir = metadata.is_dir();
+        // Junked paths leave nothing to record for a directory
+        if !(is_dir && self.opts.junk_paths) {
+            let name = entry_name(path, is_dir, self.opts);
+            if !name.is_empty() && !matches_any(self.excludes, &name) {
+                let mtime = FileTime::from_last_modification_time(&metadata).unix_seconds();
+                self.entries.push(Entry {
+                    name,
+                    source: Source::File(path.to_path_buf()),
+                    method: 0,
+                    crc: 0,
+                    compressed_size: 0,
+                    size: 0,
+                    dos_time: dos_time_from_unix(mtime),
+                    mtime: Some(
+                        cmp::max(i64::from(i32::MIN), cmp::min(mtime, i64::from(i32::MAX))) as i32,
+                    ),
+                    external_attributes: external_attributes(metadata.permissions().mode()),
+                    offset: 0,
+                });
+            }
+        }
+
+        if is_dir && self.opts.recurse {
+            let mut children =
+                match fs::read_dir(path).and_then(|dir| dir.collect::<Result<Vec<_>, _>>()) {
+                    Ok(children) => children,
+                    Err(err) => {
+                        eprintln!("zip warning: {}: {}", path.display(), err);
+                        self.failed = true;
+                        return;
+                    }
+                };
+            children.sort_by_key(|child| child.file_name());
+            for child in children {
+                self.add(&child.path());
+            }
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: zip [-rjuq0-9] [-n SUFFIXES] ARCHIVE[.zip] FILE... [-x PATTERN...]");
+    eprintln!("       zip -d ARCHIVE[.zip] PATTERN... [-x PATTERN...]");
+    process::exit(16);
+}
+
+fn main() {
+    let mut opts = Options {
+        recurse: false,
+        junk_paths: false,
+        update: false,
+        delete: false,
+        level: 6,
+        quiet: false,
+        store_suffixes: STORE_SUFFIXES.iter().map(|s| s.to_string()).collect(),
+    };
+    let mut archive_name = None;
+    let mut operands = Vec::new();
+    let mut excludes = Vec::new();
+    let mut excluding = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            print!("{}", MAN_PAGE);
+            return;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            if excluding {
+                excludes.push(arg.chars().collect::<Vec<_>>());
+            } else if archive_name.is_none() {
+                archive_name = Some(arg);
+            } else {
+                operands.push(arg);
+            }
+            continue;
+        }
+        excluding = false;
+        for (i, c) in arg.char_indices().skip(1) {
+            match c {
+                'r' => opts.recurse = true,
+                'j' => opts.junk_paths = true,
+                'u' => opts.update = true,
+                'd' => opts.delete = true,
+                'q' => opts.quiet = true,
+                'x' => excluding = true,
+                '0'..='9' => opts.level = c as u32 - '0' as u32,
+                'n' => {
+                    // The suffixes are the rest of this argument or the next one
+                    let suffixes = if i + 1 < arg.len() {
+                        arg[i + 1..].to_string()
+                    } else {
+                        args.next().unwrap_or_else(|| usage())
+                    };
+                    opts.store_suffixes = suffixes
+                        .split(':')
+                        .filter(|suffix| !suffix.is_empty())
+                        .map(|suffix| suffix.to_string())
+                        .collect();
+                    break;
+                }
+                'h' => {
+                    print!("{}", MAN_PAGE);
+                    return;
+                }
+                _ => {
+                    eprintln!("zip error: invalid option -- '{}'", c);
+                    usage();
+                }
+            }
+        }
+    }
+
+    let archive_name = archive_name.unwrap_or_else(|| usage());
+    let mut archive_path = PathBuf::from(&archive_name);
+    if archive_path.extension().is_none() {
+        archive_path.set_extension("zip");
+    }
+    if operands.is_empty() {
+        eprintln!("zip error: Nothing to do! ({})", archive_path.display());
+        process::exit(12);
+    }
+
+    let exists = archive_path.exists();
+    let mut entries = if exists {
+        match read_archive(&archive_path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!(
+                    "zip error: {}: Zip file structure invalid: {}",
+                    archive_path.display(),
+                    err
+                );
+                process::exit(3);
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let mut changed = false;
+    let mut failed = false;
+    let mut updated = Vec::new();
+
+    if opts.delete {
+        let patterns: Vec<Vec<char>> = operands.iter().map(|op| op.chars().collect()).collect();
+        let mut found = vec![false; patterns.len()];
+        entries.retain(|entry| {
+            let name: Vec<char> = String::from_utf8_lossy(&entry.name).chars().collect();
+            let mut matched = false;
+            for (pattern, found) in patterns.iter().zip(found.iter_mut()) {
+                if wildcard_match(pattern, &name) {
+                    *found = true;
+                    matched = true;
+                }
+            }
+            if !matched || matches_any(&excludes, &entry.name) {
+                return true;
+            }
+            if !opts.quiet {
+                println!("deleting: {}", String::from_utf8_lossy(&entry.name));
+            }
+            changed = true;
+            false
+        });
+        for (operand, found) in operands.iter().zip(found) {
+            if !found {
+                eprintln!("zip warning: name not matched: {}", operand);
+            }
+        }
+    } else {
+        let archive_id = fs::metadata(&archive_path)
+            .ok()
+            .map(|metadata| (metadata.dev(), metadata.ino()));
+        let mut inputs = Inputs {
+            opts: &opts,
+            excludes: &excludes,
+            archive: archive_id,
+            entries: Vec::new(),
+            failed: false,
+        };
+        for operand in &operands {
+            inputs.add(Path::new(OsStr::new(operand)));
+        }
+        failed = inputs.failed;
+
+        for input in inputs.entries {
+            match entries.iter().position(|entry| entry.name == input.name) {
+                Some(i) => {
+                    // Both times are in the MS-DOS format, which orders like the date it holds
+                    if !opts.update || input.dos_time > entries[i].dos_time {
+                        updated.push(input.name.clone());
+                        entries[i] = input;
+                        changed = true;
+                    }
+                }
+                None => {
+                    entries.push(input);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    if !changed {
+        if !failed {
+            eprintln!("zip warning: Nothing to do!");
+        }
+        process::exit(if failed { 18 } else { 12 });
+    }
+
+    let result = TempFile::create(&archive_path, 0o644).and_then(|mut temp| {
+        let mut archive = if exists {
+            let archive = fs::File::open(&archive_path)?;
+            temp.file
+                .set_permissions(archive.metadata()?.permissions())?;
+            Some(archive)
+        } else {
+            None
+        };
+        for entry in &mut entries {
+            let name = String::from_utf8_lossy(&entry.name).into_owned();
+            let context = |err: io::Error| io::Error::new(err.kind(), format!("{}: {}", name, err));
+            if let Source::Archive(ref member) = entry.source {
+                let archive = archive.as_mut().expect("member copied without an archive");
+                entry.offset = copy_member(member, archive, &mut temp.file).map_err(context)?;
+                continue;
+            }
+            let how = write_entry(entry, &mut temp.file, &opts).map_err(context)?;
+            if !opts.quiet {
+                let verb = if updated.contains(&entry.name) {
+                    "updating"
+                } else {
+                    "  adding"
+                };
+                println!("{}: {} ({})", verb, name, how);
+            }
+        }
+        write_central_directory(&entries, &mut temp.file)?;
+        temp.file.flush()?;
+        temp.persist(&archive_path)
+    });
+    if let Err(err) = result {
+        eprintln!("zip error: {}: {}", archive_path.display(), err);
+        process::exit(14);
+    }
+    if failed {
+        process::exit(18);
+    }
+}
@@ -0,0 +1,9 @@
+use libflate::lz77::MAX_WINDOW_SIZE;
+use std::cmp;
+
+/// The LZ77 window used for a compression level from 1 to 9. libflate has no tunable match
+/// search, so the level only selects the window: -1 uses 1 KiB and -6 and above use the full
+/// 32 KiB.
+pub fn window_size(level: u32) -> u16 {
+    cmp::min(512 << level, MAX_WINDOW_SIZE as u32) as u16
+}
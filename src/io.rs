@@ -0,0 +1,13 @@
+use std::io::{Read, Result};
+
+/// Read until `buf` is full or the end of input, returning the number of bytes read
+pub fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            count => len += count,
+        }
+    }
+    Ok(len)
+}
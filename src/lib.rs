@@ -0,0 +1,13 @@
+//! Code shared between the utilities
+
+extern crate bzip2;
+extern crate crc32fast;
+extern crate libflate;
+
+pub mod date;
+pub mod deflate;
+pub mod io;
+pub mod links;
+pub mod temp_file;
+pub mod wildcard;
+pub mod zip_archive;
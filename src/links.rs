@@ -0,0 +1,39 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub fn escape_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "refusing to extract outside of the destination",
+    )
+}
+
+/// Refuse to go through a symbolic link that leads outside of the destination `root`, which an
+/// earlier member of the archive may have created. The last component is only checked if `last`
+/// is set, files replace an existing last component rather than writing through it.
+pub fn check_links(root: &Path, relative: &Path, last: bool) -> io::Result<()> {
+    let components: Vec<_> = relative.components().collect();
+    let count = if last {
+        components.len()
+    } else {
+        components.len().saturating_sub(1)
+    };
+    let mut path = root.to_path_buf();
+    for component in &components[..count] {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                // A dangling link would be followed when creating what it points to
+                match fs::canonicalize(&path) {
+                    Ok(target) if target.starts_with(root) => (),
+                    _ => return Err(escape_error()),
+                }
+            }
+            Ok(_) => (),
+            // Nothing below a missing component can be a link yet
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// An output file being written under a temporary name in the directory of its final path. It is
+/// removed again unless `persist` renames it into place, so a failure never leaves a truncated
+/// output behind.
+pub struct TempFile {
+    pub file: File,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// Create the temporary file for `dest` with the permission bits `mode`
+    pub fn create(dest: &Path, mode: u32) -> io::Result<TempFile> {
+        let name = dest.file_name().unwrap_or_default().to_string_lossy();
+        let path = dest.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&path)?;
+        Ok(TempFile {
+            file,
+            path,
+            persisted: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rename the file to `dest`, replacing whatever is there
+    pub fn persist(mut self, dest: &Path) -> io::Result<()> {
+        fs::rename(&self.path, dest)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+/// Match `name` against a shell wildcard pattern supporting `*`, `?`, `[...]` and `\` escapes.
+/// Unlike in shells, `*` and `?` also match `/`, as they do in GNU tar and Info-ZIP.
+pub fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    let mut p = 0;
+    let mut n = 0;
+    // Position after the last `*` and the name position it is currently matched up to
+    let mut backtrack = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match class_match(&pattern[p..], name[n]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                None if name[n] == '[' => Some(1),
+                None => None,
+            },
+            Some('\\') if pattern.get(p + 1) == Some(&name[n]) => Some(2),
+            Some(&c) if c == name[n] => Some(1),
+            _ => None,
+        };
+        match step {
+            Some(len) => {
+                p += len;
+                n += 1;
+            }
+            None => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match a character against the `[...]` class at the start of `pattern`, returning whether it
+/// matched and the length of the class, or `None` if the class is not terminated
+fn class_match(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = pattern.get(i) == Some(&'!') || pattern.get(i) == Some(&'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while let Some(&start) = pattern.get(i) {
+        if start == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-')
+            && matches!(pattern.get(i + 2), Some(&end) if end != ']')
+        {
+            matched |= start <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+    None
+}
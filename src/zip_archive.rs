@@ -0,0 +1,424 @@
+use bzip2::read::BzDecoder;
+use crc32fast;
+use date::days_from_civil;
+use libflate::deflate::Decoder as DeflateDecoder;
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// File type bits of a Unix mode
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// General purpose flags
+pub const FLAG_ENCRYPTED: u16 = 1;
+pub const FLAG_DESCRIPTOR: u16 = 1 << 3;
+pub const FLAG_UTF8: u16 = 1 << 11;
+
+/// The Unix host system in the "version made by" field
+pub const HOST_UNIX: u16 = 3;
+
+/// Extra fields
+pub const EXTRA_ZIP64: u16 = 0x0001;
+pub const EXTRA_TIMESTAMP: u16 = 0x5455;
+pub const EXTRA_UNIX_OLD: u16 = 0x5855;
+pub const EXTRA_UNICODE_PATH: u16 = 0x7075;
+
+/// Characters 0x80 to 0xFF of code page 437, the encoding of names without the UTF-8 flag
+static CP437: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+                      ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+                      αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+pub fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+pub fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+pub fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from(le_u32(bytes)) | u64::from(le_u32(&bytes[4..])) << 32
+}
+
+pub fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Find where the data of the extra field with the given id is in `extra`
+pub fn extra_field_range(extra: &[u8], id: u16) -> Option<Range<usize>> {
+    let mut pos = 0;
+    while extra.len() >= pos + 4 {
+        let data = pos + 4..pos + 4 + le_u16(&extra[pos + 2..]) as usize;
+        if data.end > extra.len() {
+            return None;
+        }
+        if le_u16(&extra[pos..]) == id {
+            return Some(data);
+        }
+        pos = data.end;
+    }
+    None
+}
+
+/// Find the data of the extra field with the given id
+pub fn extra_field(extra: &[u8], id: u16) -> Option<&[u8]> {
+    extra_field_range(extra, id).map(|range| &extra[range])
+}
+
+/// Decode a name, which is UTF-8 if flagged and CP437 otherwise. Info-ZIP adds a UTF-8
+/// version of CP437 names in an extra field, which is used if it still belongs to the name.
+fn decode_name(raw: &[u8], flags: u16, extra: &[u8]) -> String {
+    if flags & FLAG_UTF8 != 0 {
+        return String::from_utf8_lossy(raw).into_owned();
+    }
+    if let Some(field) = extra_field(extra, EXTRA_UNICODE_PATH) {
+        if field.len() > 5 && field[0] == 1 && le_u32(&field[1..]) == crc32fast::hash(raw) {
+            if let Ok(name) = std::str::from_utf8(&field[5..]) {
+                return name.to_string();
+            }
+        }
+    }
+    raw.iter()
+        .map(|&b| {
+            if b < 0x80 {
+                b as char
+            } else {
+                CP437.chars().nth(usize::from(b - 0x80)).unwrap_or('?')
+            }
+        })
+        .collect()
+}
+
+/// A member as described by the central directory
+pub struct Member {
+    pub name: String,
+    pub made_by: u16,
+    pub flags: u16,
+    pub method: u16,
+    /// MS-DOS date in the high half and time in the low half
+    pub dos_time: u32,
+    pub crc: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    pub external_attributes: u32,
+    pub header_offset: u64,
+    pub extra: Vec<u8>,
+    /// The central directory record as it was read, for copying the member into another archive
+    pub record: Vec<u8>,
+}
+
+impl Member {
+    /// The name as it is stored, without decoding
+    pub fn raw_name(&self) -> &[u8] {
+        &self.record[46..46 + le_u16(&self.record[28..]) as usize]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    /// The Unix mode recorded by Unix hosts, or one derived from the MS-DOS attributes
+    pub fn unix_mode(&self) -> u32 {
+        let mode = self.external_attributes >> 16;
+        if self.made_by >> 8 == HOST_UNIX && mode != 0 {
+            return mode;
+        }
+        let mut mode = if self.is_dir() || self.external_attributes & 0x10 != 0 {
+            S_IFDIR | 0o775
+        } else {
+            S_IFREG | 0o664
+        };
+        if self.external_attributes & 0x01 != 0 {
+            // Read-only
+            mode &= !0o222;
+        }
+        mode
+    }
+
+    /// The MS-DOS date and time fields: year, month, day, hour, minute and second
+    pub fn dos_fields(&self) -> [i64; 6] {
+        let date = i64::from(self.dos_time >> 16);
+        let time = i64::from(self.dos_time & 0xFFFF);
+        [
+            (date >> 9) + 1980,
+            (date >> 5) & 0xF,
+            date & 0x1F,
+            time >> 11,
+            (time >> 5) & 0x3F,
+            (time & 0x1F) * 2,
+        ]
+    }
+
+    /// The modification time as Unix seconds, from an extended timestamp field if there is one
+    /// and otherwise from the MS-DOS time, taking it to be UTC
+    pub fn mtime(&self) -> i64 {
+        // The times are signed, reaching back before 1970
+        let seconds = |data: &[u8], pos: usize| {
+            data.get(pos..pos + 4)
+                .map(|bytes| i64::from(le_u32(bytes) as i32))
+        };
+        // Flags, then the times that are flagged, modification time first
+        if let Some(data) = extra_field(&self.extra, EXTRA_TIMESTAMP) {
+            if data.first().is_some_and(|flags| flags & 1 != 0) {
+                if let Some(mtime) = seconds(data, 1) {
+                    return mtime;
+                }
+            }
+        }
+        // Access time then modification time
+        if let Some(mtime) =
+            extra_field(&self.extra, EXTRA_UNIX_OLD).and_then(|data| seconds(data, 4))
+        {
+            return mtime;
+        }
+        let [year, month, day, hour, minute, second] = self.dos_fields();
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+    }
+
+    /// The path to extract to relative to the destination, and whether the name had to be
+    /// rewritten because it was absolute. Names that lead outside of the destination with `..`
+    /// components give `None`.
+    pub fn relative_path(&self) -> Option<(PathBuf, bool)> {
+        let name = self.name.split('\0').next().unwrap_or_default();
+        let mut parts: Vec<&str> = name.split('/').collect();
+        let mut rewritten = name.starts_with('/');
+        // An MS-DOS drive letter
+        if parts[0].len() == 2 && parts[0].ends_with(':') {
+            parts.remove(0);
+            rewritten = true;
+        }
+
+        let mut path = Vec::new();
+        for part in parts {
+            match part {
+                "" | "." => (),
+                ".." => {
+                    path.pop()?;
+                }
+                _ => path.push(part),
+            }
+        }
+        Some((path.iter().collect(), rewritten))
+    }
+
+    pub fn format_time(&self) -> String {
+        let [year, month, day, hour, minute, _] = self.dos_fields();
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            year, month, day, hour, minute
+        )
+    }
+
+    pub fn method_name(&self) -> String {
+        match self.method {
+            0 => "Stored".to_string(),
+            8 => {
+                // Bits 1 and 2 of the flags hold the deflate level
+                let level = ['N', 'X', 'F', 'S'][usize::from(self.flags >> 1 & 3)];
+                format!("Defl:{}", level)
+            }
+            12 => "BZip2".to_string(),
+            method => format!("Unk:{:03}", method),
+        }
+    }
+}
+
+/// Decompresses a member, checking its size and CRC-32 at the end
+pub struct MemberReader<'a> {
+    inner: Box<dyn Read + 'a>,
+    hasher: crc32fast::Hasher,
+    count: u64,
+    crc: u32,
+    size: u64,
+}
+
+impl<'a> Read for MemberReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.count += n as u64;
+        if n == 0 && !buf.is_empty() {
+            if self.count != self.size {
+                return Err(invalid("bad length"));
+            }
+            if self.hasher.clone().finalize() != self.crc {
+                return Err(invalid("bad CRC"));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// A zip archive, read from its central directory
+pub struct Archive<R> {
+    reader: R,
+    pub members: Vec<Member>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> io::Result<Archive<R>> {
+        // The end of central directory record is 22 bytes followed by a comment of up to 64 KiB
+        let len = reader.seek(SeekFrom::End(0))?;
+        let tail_len = cmp::min(len, 22 + 0xFFFF);
+        reader.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = vec![0; tail_len as usize];
+        reader.read_exact(&mut tail)?;
+        let end = (0..tail.len().saturating_sub(21))
+            .rev()
+            .find(|&i| tail[i..i + 4] == [0x50, 0x4b, 0x05, 0x06])
+            .ok_or_else(|| invalid("end of central directory not found, not a zip file"))?;
+        let record = &tail[end..];
+        let mut record_start = len - tail_len + end as u64;
+        let mut entries = u64::from(le_u16(&record[10..]));
+        let mut size = u64::from(le_u32(&record[12..]));
+        let mut offset = u64::from(le_u32(&record[16..]));
+
+        // A ZIP64 locator just before the record points to a ZIP64 record with the real values.
+        // That record is normally just before the locator, where it is looked for if data was
+        // prepended to the archive and the position in the locator is wrong.
+        if record_start >= 20 + 56 {
+            let mut locator = [0; 20];
+            reader.seek(SeekFrom::Start(record_start - 20))?;
+            reader.read_exact(&mut locator)?;
+            if locator[..4] == [0x50, 0x4b, 0x06, 0x07] {
+                let mut zip64 = [0; 56];
+                let mut found = false;
+                for &pos in &[le_u64(&locator[8..]), record_start - 20 - 56] {
+                    if pos.checked_add(56).is_some_and(|end| end <= len) {
+                        reader.seek(SeekFrom::Start(pos))?;
+                        reader.read_exact(&mut zip64)?;
+                        if zip64[..4] == [0x50, 0x4b, 0x06, 0x06] {
+                            record_start = pos;
+                            found = true;
+                            break;
+                        }
+                    }
+                }
+                if !found {
+                    return Err(invalid("ZIP64 end of central directory not found"));
+                }
+                entries = le_u64(&zip64[32..]);
+                size = le_u64(&zip64[40..]);
+                offset = le_u64(&zip64[48..]);
+            }
+        }
+
+        // The directory ends where the record starts, any difference to the offset stored in the
+        // record is data prepended to the archive, such as a self-extractor
+        let start = record_start
+            .checked_sub(size)
+            .ok_or_else(|| invalid("invalid central directory size"))?;
+        let prepended = start
+            .checked_sub(offset)
+            .ok_or_else(|| invalid("invalid central directory offset"))?;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut directory = vec![0; size as usize];
+        reader.read_exact(&mut directory)?;
+
+        let mut members = Vec::with_capacity(cmp::min(entries, 1 << 16) as usize);
+        let mut pos = 0;
+        while members.len() as u64 != entries {
+            let header = directory
+                .get(pos..pos + 46)
+                .filter(|header| header[..4] == [0x50, 0x4b, 0x01, 0x02])
+                .ok_or_else(|| invalid("central directory is truncated"))?;
+            let name_len = le_u16(&header[28..]) as usize;
+            let extra_len = le_u16(&header[30..]) as usize;
+            let comment_len = le_u16(&header[32..]) as usize;
+            let name_start = pos + 46;
+            let extra_start = name_start + name_len;
+            let next = extra_start + extra_len + comment_len;
+            if next > directory.len() {
+                return Err(invalid("central directory is truncated"));
+            }
+            let raw_name = &directory[name_start..extra_start];
+            let extra = directory[extra_start..extra_start + extra_len].to_vec();
+
+            let made_by = le_u16(&header[4..]);
+            let flags = le_u16(&header[8..]);
+            let mut compressed_size = u64::from(le_u32(&header[20..]));
+            let mut member_size = u64::from(le_u32(&header[24..]));
+            let mut header_offset = u64::from(le_u32(&header[42..]));
+
+            // Values too large for their field are in the ZIP64 field, in this order
+            if let Some(mut zip64) = extra_field(&extra, EXTRA_ZIP64) {
+                for value in [&mut member_size, &mut compressed_size, &mut header_offset] {
+                    if *value == 0xFFFF_FFFF {
+                        if zip64.len() < 8 {
+                            return Err(invalid("ZIP64 extra field is truncated"));
+                        }
+                        *value = le_u64(zip64);
+                        zip64 = &zip64[8..];
+                    }
+                }
+            }
+
+            let mut name = decode_name(raw_name, flags, &extra);
+            // MS-DOS and Windows tools may separate directories with backslashes
+            if made_by >> 8 != HOST_UNIX {
+                name = name.replace('\\', "/");
+            }
+            let header_offset = header_offset
+                .checked_add(prepended)
+                .ok_or_else(|| invalid("invalid local header offset"))?;
+            members.push(Member {
+                name,
+                made_by,
+                flags,
+                method: le_u16(&header[10..]),
+                dos_time: le_u32(&header[12..]),
+                crc: le_u32(&header[16..]),
+                compressed_size,
+                size: member_size,
+                external_attributes: le_u32(&header[38..]),
+                header_offset,
+                extra,
+                record: directory[pos..next].to_vec(),
+            });
+            pos = next;
+        }
+
+        Ok(Archive { reader, members })
+    }
+
+    /// Read the data of a member
+    pub fn open(&mut self, index: usize) -> io::Result<MemberReader<'_>> {
+        let member = &self.members[index];
+        self.reader.seek(SeekFrom::Start(member.header_offset))?;
+        let mut header = [0; 30];
+        self.reader.read_exact(&mut header)?;
+        if header[..4] != [0x50, 0x4b, 0x03, 0x04] {
+            return Err(invalid("bad local header"));
+        }
+        let skip = i64::from(le_u16(&header[26..])) + i64::from(le_u16(&header[28..]));
+        self.reader.seek(SeekFrom::Current(skip))?;
+
+        let data = (&mut self.reader).take(member.compressed_size);
+        let inner: Box<dyn Read> = match member.method {
+            0 => Box::new(data),
+            8 => Box::new(DeflateDecoder::new(data)),
+            12 => Box::new(BzDecoder::new(data)),
+            method => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unsupported compression method {}", method),
+                ))
+            }
+        };
+        Ok(MemberReader {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            count: 0,
+            crc: member.crc,
+            size: member.size,
+        })
+    }
+}
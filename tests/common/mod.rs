@@ -0,0 +1,75 @@
+//! Fixtures shared between the integration tests
+
+// Each test crate only uses some of these
+#![allow(dead_code)]
+
+use crc32fast;
+
+/// Unix modes of zip members
+pub const FILE: u32 = 0o100644;
+pub const DIR: u32 = 0o040755;
+pub const LINK: u32 = 0o120777;
+
+/// General purpose flags of zip members
+pub const FLAG_ENCRYPTED: u16 = 1;
+pub const FLAG_DESCRIPTOR: u16 = 1 << 3;
+
+/// Build a zip archive of stored members, each a name, general purpose flags, a Unix mode and
+/// the data. A link's data is its target. Members flagged with a data descriptor have zero
+/// sizes and CRC-32 in their local header.
+pub fn zip(members: &[(&str, u16, u32, &[u8])]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let mut directory = Vec::new();
+    for &(name, flags, mode, data) in members {
+        let offset = zip.len() as u32;
+        let crc = crc32fast::hash(data);
+        let size = data.len() as u32;
+        let descriptor = flags & FLAG_DESCRIPTOR != 0;
+
+        zip.extend_from_slice(b"PK\x03\x04");
+        zip.extend_from_slice(&[10, 0]);
+        zip.extend_from_slice(&flags.to_le_bytes());
+        // Method, time and date
+        zip.extend_from_slice(&[0, 0, 0, 0, 0x21, 0]);
+        for value in &[crc, size, size] {
+            zip.extend_from_slice(&(if descriptor { 0 } else { *value }).to_le_bytes());
+        }
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&[0, 0]);
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(data);
+        if descriptor {
+            zip.extend_from_slice(b"PK\x07\x08");
+            for value in &[crc, size, size] {
+                zip.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        directory.extend_from_slice(b"PK\x01\x02");
+        // Made by Unix, version needed
+        directory.extend_from_slice(&[20, 3, 10, 0]);
+        directory.extend_from_slice(&flags.to_le_bytes());
+        // Method, time and date
+        directory.extend_from_slice(&[0, 0, 0, 0, 0x21, 0]);
+        for value in &[crc, size, size] {
+            directory.extend_from_slice(&value.to_le_bytes());
+        }
+        directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // Extra field and comment lengths, disk number and internal attributes
+        directory.extend_from_slice(&[0; 8]);
+        directory.extend_from_slice(&(mode << 16).to_le_bytes());
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let start = zip.len() as u32;
+    zip.extend_from_slice(&directory);
+    zip.extend_from_slice(b"PK\x05\x06");
+    zip.extend_from_slice(&[0; 4]);
+    zip.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&start.to_le_bytes());
+    zip.extend_from_slice(&[0, 0]);
+    zip
+}
@@ -0,0 +1,313 @@
+extern crate assert_cmd;
+extern crate crc32fast;
+extern crate libflate;
+extern crate predicates;
+extern crate tar;
+extern crate tempfile;
+
+mod common;
+
+use assert_cmd::Command;
+use common::{zip, FLAG_ENCRYPTED};
+use libflate::gzip::Encoder as GzipEncoder;
+use predicates::prelude::*;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+use tempfile::TempDir;
+
+const FILE: EntryType = EntryType::Regular;
+const LINK: EntryType = EntryType::Symlink;
+
+/// Build a tar archive of members, each a name, a type and the data. A link's data is its
+/// target. Names are stored as they are, even if they lead outside.
+fn tarball(members: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for &(name, entry_type, data) in members {
+        let mut header = Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        if entry_type == EntryType::Symlink {
+            header
+                .set_link_name(String::from_utf8_lossy(data).as_ref())
+                .unwrap();
+            header.set_size(0);
+            header.set_cksum();
+            builder.append(&header, &[][..]).unwrap();
+        } else {
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+    }
+    builder.into_inner().unwrap()
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzipEncoder::new(Vec::new()).unwrap();
+    encoder.write_all(data).unwrap();
+    encoder.finish().into_result().unwrap()
+}
+
+fn extract(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("extract").expect("found binary");
+    cmd.current_dir(dir);
+    cmd
+}
+
+/// The names in a directory, sorted
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn several_members_get_a_directory() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[
+            ("a", 0, common::FILE, b"a\n"),
+            ("b", 0, common::FILE, b"b\n"),
+        ]),
+    )
+    .unwrap();
+    extract(dir.path())
+        .arg("test.zip")
+        .assert()
+        .success()
+        .stdout("test.zip: extracted to ./test\n");
+    assert_eq!(names(&dir.path().join("test")), ["a", "b"]);
+    assert_eq!(fs::read(dir.path().join("test/a")).unwrap(), b"a\n");
+}
+
+#[test]
+fn single_top_level_directory_is_kept() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.tar"),
+        tarball(&[("top/a", FILE, b"a\n"), ("top/b", FILE, b"b\n")]),
+    )
+    .unwrap();
+    extract(dir.path())
+        .arg("test.tar")
+        .assert()
+        .success()
+        .stdout("test.tar: extracted to ./top\n");
+    assert_eq!(names(dir.path()), ["test.tar", "top"]);
+    assert_eq!(names(&dir.path().join("top")), ["a", "b"]);
+}
+
+#[test]
+fn taken_name_is_numbered() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.tar"),
+        tarball(&[("a", FILE, b"a\n"), ("b", FILE, b"b\n")]),
+    )
+    .unwrap();
+    extract(dir.path()).arg("test.tar").assert().success();
+    extract(dir.path())
+        .arg("test.tar")
+        .assert()
+        .success()
+        .stdout("test.tar: extracted to ./test-1\n");
+    assert_eq!(names(dir.path()), ["test", "test-1", "test.tar"]);
+}
+
+#[test]
+fn format_is_detected_by_contents() {
+    let dir = TempDir::new().unwrap();
+    // A gzipped tar archive despite its name
+    fs::write(
+        dir.path().join("archive.zip"),
+        gzip(&tarball(&[("a", FILE, b"a\n"), ("b", FILE, b"b\n")])),
+    )
+    .unwrap();
+    extract(dir.path())
+        .arg("archive.zip")
+        .assert()
+        .success()
+        .stdout("archive.zip: extracted to ./archive\n");
+    assert_eq!(names(&dir.path().join("archive")), ["a", "b"]);
+}
+
+#[test]
+fn single_compressed_file_is_decompressed() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("notes.txt.gz"), gzip(b"notes\n")).unwrap();
+    extract(dir.path())
+        .arg("notes.txt.gz")
+        .assert()
+        .success()
+        .stdout("notes.txt.gz: extracted to ./notes.txt\n");
+    assert_eq!(fs::read(dir.path().join("notes.txt")).unwrap(), b"notes\n");
+}
+
+#[test]
+fn escaping_tar_members_are_skipped() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("outside")).unwrap();
+    fs::write(
+        dir.path().join("test.tar"),
+        tarball(&[
+            ("../evil", FILE, b"evil\n"),
+            ("/abs", FILE, b"evil\n"),
+            ("link", LINK, b"../outside"),
+            ("link/evil", FILE, b"evil\n"),
+            ("good", FILE, b"good\n"),
+        ]),
+    )
+    .unwrap();
+    extract(dir.path())
+        .arg("test.tar")
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("skipping ../evil: outside of the destination")
+                .and(predicate::str::contains(
+                    "skipping /abs: outside of the destination",
+                ))
+                .and(predicate::str::contains("skipping link/evil")),
+        );
+    assert!(!dir.path().join("evil").exists());
+    assert!(!dir.path().join("outside/evil").exists());
+    assert_eq!(fs::read(dir.path().join("test/good")).unwrap(), b"good\n");
+}
+
+#[test]
+fn escaping_and_encrypted_zip_members_are_skipped() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[
+            ("../evil", 0, common::FILE, b"evil\n"),
+            (
+                "secret",
+                FLAG_ENCRYPTED,
+                common::FILE,
+                b"not really encrypted",
+            ),
+            ("plain", 0, common::FILE, b"plain\n"),
+        ]),
+    )
+    .unwrap();
+    extract(dir.path())
+        .arg("test.zip")
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("skipping ../evil: outside of the destination")
+                .and(predicate::str::contains("skipping secret: encrypted")),
+        )
+        .stdout("test.zip: extracted to ./plain\n");
+    assert!(!dir.path().join("evil").exists());
+    assert_eq!(fs::read(dir.path().join("plain")).unwrap(), b"plain\n");
+}
+
+#[test]
+fn zip_files_replace_links_instead_of_following_them() {
+    let dir = TempDir::new().unwrap();
+    let target = dir.path().join("target");
+    fs::write(&target, b"keep\n").unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+    fs::create_dir(dir.path().join("out")).unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[
+            ("a", 0, common::LINK, target.to_str().unwrap().as_bytes()),
+            ("a", 0, 0o100755, b"replaced\n"),
+        ]),
+    )
+    .unwrap();
+    extract(dir.path())
+        .args(["-d", "out", "test.zip"])
+        .assert()
+        .success();
+    assert_eq!(fs::read(&target).unwrap(), b"keep\n");
+    assert_eq!(fs::metadata(&target).unwrap().mode() & 0o7777, 0o600);
+    let extracted = dir.path().join("out/a");
+    assert!(!fs::symlink_metadata(&extracted)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read(&extracted).unwrap(), b"replaced\n");
+}
+
+#[test]
+fn special_mode_bits_are_not_restored() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[
+            ("zip", 0, 0o106755, b"zip\n"),
+            ("b", 0, common::FILE, b"b\n"),
+        ]),
+    )
+    .unwrap();
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o7755);
+    header.set_size(4);
+    builder
+        .append_data(&mut header, "tar", &b"tar\n"[..])
+        .unwrap();
+    fs::write(dir.path().join("test.tar"), builder.into_inner().unwrap()).unwrap();
+
+    extract(dir.path())
+        .args(["test.zip", "test.tar"])
+        .assert()
+        .success();
+    for path in &["test/zip", "tar"] {
+        let mode = fs::metadata(dir.path().join(path)).unwrap().mode();
+        assert_eq!(mode & 0o7777, 0o755, "{}", path);
+    }
+}
+
+#[test]
+fn failing_members_are_reported_and_the_rest_extracted() {
+    let dir = TempDir::new().unwrap();
+    let mut archive = zip(&[
+        ("unsupported", 0, common::FILE, b"?"),
+        ("good", 0, common::FILE, b"good\n"),
+    ]);
+    // Give the first member a compression method that is not supported, in its local header
+    // and its central directory record
+    archive[8] = 99;
+    let central = archive
+        .windows(4)
+        .position(|window| window == b"PK\x01\x02")
+        .unwrap();
+    archive[central + 10] = 99;
+    fs::write(dir.path().join("test.zip"), archive).unwrap();
+    extract(dir.path()).arg("test.zip").assert().code(1).stderr(
+        predicate::str::contains("test.zip: unsupported: ").and(predicate::str::contains(
+            "test.zip: 1 member(s) could not be extracted",
+        )),
+    );
+    assert_eq!(fs::read(dir.path().join("good")).unwrap(), b"good\n");
+
+    // A file standing where a directory is needed
+    fs::write(
+        dir.path().join("test.tar"),
+        tarball(&[
+            ("d", FILE, b"file\n"),
+            ("d/a", FILE, b"a\n"),
+            ("good", FILE, b"good\n"),
+        ]),
+    )
+    .unwrap();
+    extract(dir.path()).arg("test.tar").assert().code(1).stderr(
+        predicate::str::contains("test.tar: d/a: ").and(predicate::str::contains(
+            "test.tar: 1 member(s) could not be extracted",
+        )),
+    );
+    assert_eq!(names(&dir.path().join("test")), ["d", "good"]);
+}
@@ -0,0 +1,418 @@
+extern crate assert_cmd;
+extern crate filetime;
+extern crate libflate;
+extern crate tempfile;
+
+use assert_cmd::Command;
+use filetime::FileTime;
+use libflate::gzip::Decoder;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn run(name: &str, dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin(name).expect("found binary");
+    cmd.current_dir(dir);
+    cmd
+}
+
+/// Text of pseudo-random words, compressible but with matches reaching back across the blocks
+/// that -p deflates separately
+fn words(len: usize) -> Vec<u8> {
+    const WORDS: [&str; 8] = [
+        "alpha ", "bravo ", "charlie ", "delta ", "echo ", "foxtrot ", "golf ", "hotel\n",
+    ];
+    let mut state = 1u32;
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        data.extend_from_slice(WORDS[(state >> 16) as usize % WORDS.len()].as_bytes());
+        data.push((state >> 24) as u8);
+    }
+    data.truncate(len);
+    data
+}
+
+#[test]
+fn parallel_round_trip() {
+    let dir = TempDir::new().unwrap();
+    // Several rounds of 128 KiB blocks over three threads, ending in a partial block
+    let data = words(1_000_000);
+    fs::write(dir.path().join("data"), &data).unwrap();
+
+    run("gzip", dir.path())
+        .args(["-p", "3", "data"])
+        .assert()
+        .success();
+    assert!(!dir.path().join("data").exists());
+    run("gunzip", dir.path()).arg("data.gz").assert().success();
+    assert_eq!(fs::read(dir.path().join("data")).unwrap(), data);
+}
+
+#[test]
+fn parallel_round_trip_of_whole_blocks() {
+    let dir = TempDir::new().unwrap();
+    let data = words(4 * 128 * 1024);
+    fs::write(dir.path().join("data"), &data).unwrap();
+
+    run("gzip", dir.path())
+        .args(["-p2", "-k", "data"])
+        .assert()
+        .success();
+    let output = run("gunzip", dir.path())
+        .args(["-c", "data.gz"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, data);
+}
+
+#[test]
+fn processes_only_for_gzip() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("data"), b"data\n").unwrap();
+    for name in &["xz", "bzip2"] {
+        run(name, dir.path())
+            .args(["-p", "2", "data"])
+            .assert()
+            .failure();
+        run(name, dir.path())
+            .args(["--processes=2", "data"])
+            .assert()
+            .failure();
+        assert!(dir.path().join("data").exists());
+    }
+}
+
+#[test]
+fn length_errors_are_detected() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("data"), b"data\n").unwrap();
+    run("gzip", dir.path()).arg("data").assert().success();
+    let mut compressed = fs::read(dir.path().join("data.gz")).unwrap();
+    let len = compressed.len();
+    compressed[len - 4] ^= 1;
+    fs::write(dir.path().join("data.gz"), &compressed).unwrap();
+
+    run("gunzip", dir.path())
+        .args(["-c", "data.gz"])
+        .assert()
+        .failure();
+    run("gzip", dir.path())
+        .args(["-t", "data.gz"])
+        .assert()
+        .failure();
+    run("gunzip", dir.path()).arg("data.gz").assert().failure();
+    assert!(!dir.path().join("data").exists());
+    assert!(dir.path().join("data.gz").exists());
+}
+
+#[test]
+fn parallel_block_boundaries() {
+    let dir = TempDir::new().unwrap();
+    let mut random = words(300_000);
+    for (i, byte) in random.iter_mut().enumerate() {
+        *byte = byte.wrapping_mul(31).wrapping_add(((i * i) >> 7) as u8);
+    }
+    let inputs = [
+        Vec::new(),
+        b"a".to_vec(),
+        // Long matches of the maximum length, and literals that are barely compressible
+        vec![b'z'; 3 * 128 * 1024 + 1],
+        random,
+    ];
+    for data in &inputs {
+        let output = run("gzip", dir.path())
+            .args(["-c", "-p", "3"])
+            .write_stdin(data.clone())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        // Every block must be re-parsed correctly for the stream to decode as one member
+        let mut decoder = Decoder::new(&output.stdout[..]).unwrap();
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded, data);
+        assert!(decoder.into_inner().is_empty());
+    }
+}
+
+#[test]
+fn filter_and_stdout() {
+    let dir = TempDir::new().unwrap();
+    let output = run("gzip", dir.path())
+        .write_stdin(&b"piped\n"[..])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    run("gunzip", dir.path())
+        .arg("-")
+        .write_stdin(output.stdout)
+        .assert()
+        .success()
+        .stdout("piped\n");
+
+    fs::write(dir.path().join("data"), b"data\n").unwrap();
+    let output = run("gzip", dir.path())
+        .args(["-c", "data"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(dir.path().join("data").exists());
+    assert!(!dir.path().join("data.gz").exists());
+    run("gunzip", dir.path())
+        .write_stdin(output.stdout)
+        .assert()
+        .success()
+        .stdout("data\n");
+}
+
+#[test]
+fn levels_and_decompress_modes() {
+    let dir = TempDir::new().unwrap();
+    let data = words(200_000);
+    fs::write(dir.path().join("data"), &data).unwrap();
+
+    let mut sizes = Vec::new();
+    for level in &["-1", "-9", "--fast", "--best"] {
+        let output = run("gzip", dir.path())
+            .args(["-c", level, "data"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        sizes.push(output.stdout.len());
+        fs::write(dir.path().join("data.gz"), &output.stdout).unwrap();
+        for &(name, args) in &[
+            ("gzip", &["-dc", "data.gz"][..]),
+            ("gunzip", &["-c", "data.gz"][..]),
+            ("zcat", &["data.gz"][..]),
+        ] {
+            let output = run(name, dir.path()).args(args).output().unwrap();
+            assert!(output.status.success());
+            assert_eq!(output.stdout, data);
+        }
+    }
+    assert!(sizes[1] < sizes[0]);
+    assert_eq!(sizes[0], sizes[2]);
+    assert_eq!(sizes[1], sizes[3]);
+}
+
+#[test]
+fn keep_and_force() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("data"), b"data\n").unwrap();
+    run("gzip", dir.path())
+        .args(["-k", "data"])
+        .assert()
+        .success();
+    assert!(dir.path().join("data").exists());
+
+    // An existing output is left alone without -f
+    run("gzip", dir.path()).arg("data").assert().failure();
+    assert!(dir.path().join("data").exists());
+    fs::write(dir.path().join("data"), b"changed\n").unwrap();
+    run("gunzip", dir.path()).arg("data.gz").assert().failure();
+    assert_eq!(fs::read(dir.path().join("data")).unwrap(), b"changed\n");
+
+    run("gunzip", dir.path())
+        .args(["-f", "data.gz"])
+        .assert()
+        .success();
+    assert_eq!(fs::read(dir.path().join("data")).unwrap(), b"data\n");
+    assert!(!dir.path().join("data.gz").exists());
+}
+
+#[test]
+fn name_and_time_in_header() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("data"), b"data\n").unwrap();
+    filetime::set_file_mtime(
+        dir.path().join("data"),
+        FileTime::from_unix_time(1_000_000_000, 0),
+    )
+    .unwrap();
+    run("gzip", dir.path())
+        .args(["-k", "data"])
+        .assert()
+        .success();
+    let mtime = |name: &str| {
+        let meta = fs::metadata(dir.path().join(name)).unwrap();
+        FileTime::from_last_modification_time(&meta).unix_seconds()
+    };
+    assert_eq!(mtime("data.gz"), 1_000_000_000);
+
+    // -N restores the stored name and time instead of using the file name
+    fs::rename(dir.path().join("data.gz"), dir.path().join("renamed.gz")).unwrap();
+    fs::remove_file(dir.path().join("data")).unwrap();
+    run("gunzip", dir.path())
+        .args(["-N", "renamed.gz"])
+        .assert()
+        .success();
+    assert_eq!(fs::read(dir.path().join("data")).unwrap(), b"data\n");
+    assert!(!dir.path().join("renamed").exists());
+    assert_eq!(mtime("data"), 1_000_000_000);
+
+    // -n leaves them out, so the output does not depend on them
+    let compress = |dir: &Path| {
+        let output = run("gzip", dir).args(["-cn", "data"]).output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let other = TempDir::new().unwrap();
+    fs::copy(dir.path().join("data"), other.path().join("data")).unwrap();
+    let first = compress(dir.path());
+    assert_eq!(compress(other.path()), first);
+    assert!(!first.windows(4).any(|name| name == b"data"));
+}
+
+#[test]
+fn test_and_list_multiple_members() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"hello\n").unwrap();
+    fs::write(dir.path().join("b"), b"world\n").unwrap();
+    run("gzip", dir.path()).args(["a", "b"]).assert().success();
+    let mut joined = fs::read(dir.path().join("a.gz")).unwrap();
+    joined.extend(fs::read(dir.path().join("b.gz")).unwrap());
+    fs::write(dir.path().join("ab.gz"), &joined).unwrap();
+
+    run("gzip", dir.path())
+        .args(["-t", "ab.gz"])
+        .assert()
+        .success()
+        .stdout("");
+    let output = run("gzip", dir.path())
+        .args(["-l", "ab.gz"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    let fields = listing
+        .lines()
+        .nth(1)
+        .unwrap()
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    assert_eq!(fields[0], joined.len().to_string());
+    assert_eq!(fields[1], "12");
+    assert_eq!(fields[3], "ab");
+
+    // A damaged CRC in the second member fails the test
+    let len = joined.len();
+    joined[len - 8] ^= 1;
+    fs::write(dir.path().join("ab.gz"), &joined).unwrap();
+    run("gzip", dir.path())
+        .args(["-t", "ab.gz"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn suffixes() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("data.tar"), b"tar\n").unwrap();
+    run("gzip", dir.path()).arg("data.tar").assert().success();
+    fs::rename(dir.path().join("data.tar.gz"), dir.path().join("data.tgz")).unwrap();
+    run("gunzip", dir.path()).arg("data.tgz").assert().success();
+    assert_eq!(fs::read(dir.path().join("data.tar")).unwrap(), b"tar\n");
+
+    fs::write(dir.path().join("data"), b"data\n").unwrap();
+    run("gzip", dir.path())
+        .args(["-S", ".foo", "data"])
+        .assert()
+        .success();
+    assert!(dir.path().join("data.foo").exists());
+    run("gunzip", dir.path()).arg("data.foo").assert().code(2);
+    assert!(dir.path().join("data.foo").exists());
+    run("gunzip", dir.path())
+        .args(["-S", ".foo", "data.foo"])
+        .assert()
+        .success();
+    assert_eq!(fs::read(dir.path().join("data")).unwrap(), b"data\n");
+}
+
+#[test]
+fn decompress_multiple_members() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"hello\n").unwrap();
+    fs::write(dir.path().join("b"), b"world\n").unwrap();
+    run("gzip", dir.path()).args(["a", "b"]).assert().success();
+    let mut joined = fs::read(dir.path().join("a.gz")).unwrap();
+    joined.extend(fs::read(dir.path().join("b.gz")).unwrap());
+    fs::write(dir.path().join("ab.gz"), &joined).unwrap();
+
+    run("gunzip", dir.path()).arg("ab.gz").assert().success();
+    assert_eq!(fs::read(dir.path().join("ab")).unwrap(), b"hello\nworld\n");
+}
+
+#[test]
+fn failures_leave_no_partial_output() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), words(100_000)).unwrap();
+    fs::write(dir.path().join("b"), b"b\n").unwrap();
+    run("gzip", dir.path()).args(["a", "b"]).assert().success();
+    // Damage the end of the first file, after much of it has been written out
+    let mut compressed = fs::read(dir.path().join("a.gz")).unwrap();
+    let len = compressed.len();
+    compressed.truncate(len - 100);
+    fs::write(dir.path().join("a.gz"), &compressed).unwrap();
+
+    // The remaining files are still handled, and the failure is reported in the exit status
+    run("gunzip", dir.path())
+        .args(["a.gz", "b.gz"])
+        .assert()
+        .failure();
+    assert!(!dir.path().join("a").exists());
+    assert!(dir.path().join("a.gz").exists());
+    assert_eq!(fs::read(dir.path().join("b")).unwrap(), b"b\n");
+    assert!(!dir.path().join("b.gz").exists());
+    let leftovers = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn xz_and_bzip2_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let data = words(100_000);
+    fs::write(dir.path().join("data"), &data).unwrap();
+    for &(compress, decompress, cat, suffix) in &[
+        ("xz", "unxz", "xzcat", "xz"),
+        ("bzip2", "bunzip2", "bzcat", "bz2"),
+    ] {
+        let compressed = format!("data.{}", suffix);
+        run(compress, dir.path())
+            .args(["-k", "-9", "data"])
+            .assert()
+            .success();
+        run(compress, dir.path())
+            .args(["-t", &compressed])
+            .assert()
+            .success();
+        let output = run(cat, dir.path()).arg(&compressed).output().unwrap();
+        assert_eq!(output.stdout, data);
+
+        fs::remove_file(dir.path().join("data")).unwrap();
+        run(decompress, dir.path())
+            .arg(&compressed)
+            .assert()
+            .success();
+        assert_eq!(fs::read(dir.path().join("data")).unwrap(), data);
+        assert!(!dir.path().join(&compressed).exists());
+
+        let output = run(compress, dir.path())
+            .write_stdin(&b"piped\n"[..])
+            .output()
+            .unwrap();
+        run(compress, dir.path())
+            .arg("-d")
+            .write_stdin(output.stdout)
+            .assert()
+            .success()
+            .stdout("piped\n");
+    }
+}
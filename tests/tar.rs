@@ -0,0 +1,460 @@
+extern crate assert_cmd;
+extern crate filetime;
+extern crate predicates;
+extern crate tar;
+extern crate tempfile;
+
+use assert_cmd::Command;
+use filetime::FileTime;
+use predicates::prelude::*;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+use tempfile::TempDir;
+
+fn tar(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("tar").expect("found binary");
+    cmd.current_dir(dir);
+    cmd
+}
+
+/// The member names of `test.tar` in the directory
+fn list(dir: &Path) -> Vec<String> {
+    let output = tar(dir).args(["tf", "test.tar"]).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+/// Extract `test.tar` into a new `out` directory
+fn extract(dir: &Path) {
+    fs::create_dir(dir.join("out")).unwrap();
+    tar(dir)
+        .args(["xf", "test.tar", "-C", "out"])
+        .assert()
+        .success();
+}
+
+/// Extract `test.tar` into the existing `out` directory with extra arguments
+fn extract_with(dir: &Path, args: &[&str]) -> assert_cmd::assert::Assert {
+    tar(dir)
+        .args(["xf", "test.tar", "-C", "out"])
+        .args(args)
+        .assert()
+}
+
+fn set_mtime(path: &Path, secs: i64) {
+    filetime::set_file_mtime(path, FileTime::from_unix_time(secs, 0)).unwrap();
+}
+
+/// Write a file of 512 byte data blocks separated by holes, with more data regions than fit in
+/// the main sparse header
+fn write_sparse(path: &Path) -> Vec<u8> {
+    let mut file = File::create(path).unwrap();
+    let mut contents = Vec::new();
+    for region in 0..7u8 {
+        contents.resize(region as usize * 4096, 0);
+        contents.extend_from_slice(&[region + 1; 512]);
+    }
+    contents.resize(contents.len() + 4096, 0);
+    for (offset, block) in contents.chunks(512).enumerate() {
+        if block.iter().any(|&byte| byte != 0) {
+            file.seek(SeekFrom::Start(offset as u64 * 512)).unwrap();
+            file.write_all(block).unwrap();
+        }
+    }
+    file.set_len(contents.len() as u64).unwrap();
+    contents
+}
+
+#[test]
+fn append_adds_members() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"a\n").unwrap();
+    fs::write(dir.path().join("b"), b"b\n").unwrap();
+    tar(dir.path())
+        .args(["cf", "test.tar", "a"])
+        .assert()
+        .success();
+    tar(dir.path())
+        .args(["rf", "test.tar", "b"])
+        .assert()
+        .success();
+    assert_eq!(list(dir.path()), ["a", "b"]);
+
+    extract(dir.path());
+    assert_eq!(fs::read(dir.path().join("out/a")).unwrap(), b"a\n");
+    assert_eq!(fs::read(dir.path().join("out/b")).unwrap(), b"b\n");
+}
+
+#[test]
+fn update_adds_only_newer_files() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"old\n").unwrap();
+    set_mtime(&dir.path().join("a"), 1_000_000_000);
+    tar(dir.path())
+        .args(["cf", "test.tar", "a"])
+        .assert()
+        .success();
+
+    tar(dir.path())
+        .args(["uf", "test.tar", "a"])
+        .assert()
+        .success();
+    assert_eq!(list(dir.path()), ["a"]);
+
+    fs::write(dir.path().join("a"), b"new\n").unwrap();
+    set_mtime(&dir.path().join("a"), 1_000_000_100);
+    tar(dir.path())
+        .args(["uf", "test.tar", "a"])
+        .assert()
+        .success();
+    assert_eq!(list(dir.path()), ["a", "a"]);
+
+    extract(dir.path());
+    assert_eq!(fs::read(dir.path().join("out/a")).unwrap(), b"new\n");
+}
+
+#[test]
+fn delete_removes_members() {
+    let dir = TempDir::new().unwrap();
+    for name in &["a", "b", "c"] {
+        fs::write(dir.path().join(name), name.repeat(1000)).unwrap();
+    }
+    tar(dir.path())
+        .args(["cf", "test.tar", "a", "b", "c"])
+        .assert()
+        .success();
+    tar(dir.path())
+        .args(["--delete", "-f", "test.tar", "b"])
+        .assert()
+        .success();
+    assert_eq!(list(dir.path()), ["a", "c"]);
+
+    extract(dir.path());
+    assert_eq!(
+        fs::read(dir.path().join("out/a")).unwrap(),
+        "a".repeat(1000).as_bytes()
+    );
+    assert_eq!(
+        fs::read(dir.path().join("out/c")).unwrap(),
+        "c".repeat(1000).as_bytes()
+    );
+}
+
+#[test]
+fn append_after_extended_sparse_member() {
+    let dir = TempDir::new().unwrap();
+    let contents = write_sparse(&dir.path().join("sparse"));
+    fs::write(dir.path().join("after"), b"after\n").unwrap();
+    tar(dir.path())
+        .args(["cSf", "test.tar", "sparse"])
+        .assert()
+        .success();
+    tar(dir.path())
+        .args(["rf", "test.tar", "after"])
+        .assert()
+        .success();
+    assert_eq!(list(dir.path()), ["sparse", "after"]);
+
+    extract(dir.path());
+    assert_eq!(fs::read(dir.path().join("out/sparse")).unwrap(), contents);
+    assert_eq!(fs::read(dir.path().join("out/after")).unwrap(), b"after\n");
+}
+
+#[test]
+fn delete_after_extended_sparse_member() {
+    let dir = TempDir::new().unwrap();
+    let contents = write_sparse(&dir.path().join("sparse"));
+    fs::write(dir.path().join("a"), b"a\n").unwrap();
+    fs::write(dir.path().join("b"), b"b\n").unwrap();
+    tar(dir.path())
+        .args(["cSf", "test.tar", "sparse", "a", "b"])
+        .assert()
+        .success();
+    tar(dir.path())
+        .args(["--delete", "-f", "test.tar", "a"])
+        .assert()
+        .success();
+    assert_eq!(list(dir.path()), ["sparse", "b"]);
+
+    extract(dir.path());
+    assert_eq!(fs::read(dir.path().join("out/sparse")).unwrap(), contents);
+    assert_eq!(fs::read(dir.path().join("out/b")).unwrap(), b"b\n");
+}
+
+#[test]
+fn create_lists_members_only_when_verbose() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"a\n").unwrap();
+    let output = tar(dir.path())
+        .args(["cf", "test.tar", "a"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = tar(dir.path())
+        .args(["cvf", "test.tar", "a"])
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"a\n");
+
+    // With the archive on standard output the listing goes to standard error
+    let output = tar(dir.path()).args(["cvf", "-", "a"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stderr, b"a\n");
+    fs::write(dir.path().join("test.tar"), &output.stdout).unwrap();
+    assert_eq!(list(dir.path()), ["a"]);
+}
+
+#[test]
+fn mtime_rejects_out_of_range_times() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"a\n").unwrap();
+    for date in [
+        "2020-01-01 24:00",
+        "2020-01-01 12:60",
+        "2020-01-01 12:00:60",
+    ] {
+        tar(dir.path())
+            .args(["cf", "test.tar", "--mtime", date, "a"])
+            .assert()
+            .failure();
+    }
+    tar(dir.path())
+        .args(["cf", "test.tar", "--mtime", "2020-01-01 23:59:59", "a"])
+        .assert()
+        .success();
+    extract(dir.path());
+    let meta = fs::metadata(dir.path().join("out/a")).unwrap();
+    assert_eq!(
+        FileTime::from_last_modification_time(&meta).unix_seconds(),
+        1577923199
+    );
+}
+
+#[test]
+fn append_rejects_compression() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"a\n").unwrap();
+    tar(dir.path())
+        .args(["cf", "test.tar", "a"])
+        .assert()
+        .success();
+    for flag in ["-z", "-j", "-J"] {
+        tar(dir.path())
+            .args(["rf", "test.tar", flag, "a"])
+            .assert()
+            .failure();
+    }
+    assert_eq!(list(dir.path()), ["a"]);
+}
+
+#[test]
+fn select_members_by_name_and_wildcard() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("usr/bin")).unwrap();
+    fs::create_dir_all(dir.path().join("usr/share")).unwrap();
+    for name in &["usr/bin/foo", "usr/bin/bar", "usr/share/a", "usr/share/b"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    tar(dir.path())
+        .args(["cf", "test.tar", "--sort=name", "usr"])
+        .assert()
+        .success();
+
+    tar(dir.path())
+        .args(["tf", "test.tar", "usr/bin/foo", "usr/share/*"])
+        .assert()
+        .success()
+        .stdout("usr/bin/foo\nusr/share/a\nusr/share/b\n");
+    tar(dir.path())
+        .args(["tf", "test.tar", "usr/bin"])
+        .assert()
+        .success()
+        .stdout("usr/bin/bar\nusr/bin/foo\n");
+
+    fs::create_dir(dir.path().join("out")).unwrap();
+    tar(dir.path())
+        .args(["xf", "test.tar", "-C", "out", "usr/share/?"])
+        .assert()
+        .success();
+    assert!(dir.path().join("out/usr/share/a").exists());
+    assert!(dir.path().join("out/usr/share/b").exists());
+    assert!(!dir.path().join("out/usr/bin").exists());
+}
+
+#[test]
+fn missing_members_are_reported() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a*"), b"a\n").unwrap();
+    fs::write(dir.path().join("ab"), b"ab\n").unwrap();
+    tar(dir.path())
+        .args(["cf", "test.tar", "a*", "ab"])
+        .assert()
+        .success();
+
+    tar(dir.path())
+        .args(["tf", "test.tar", "--no-wildcards", "a*"])
+        .assert()
+        .success()
+        .stdout("a*\n");
+    tar(dir.path())
+        .args(["tf", "test.tar", "--no-wildcards", "a?", "b", "ab"])
+        .assert()
+        .failure()
+        .stdout("ab\n")
+        .stderr(predicate::str::contains("not found in archive: a?, b"));
+}
+
+/// Archive `a` with the given contents and modification time, then put `out/a` with other
+/// contents and a hard link `out/link` to it in place
+fn overwrite_setup(dir: &Path, archived_mtime: i64, existing_mtime: i64) {
+    fs::write(dir.join("a"), b"archived\n").unwrap();
+    set_mtime(&dir.join("a"), archived_mtime);
+    tar(dir).args(["cf", "test.tar", "a"]).assert().success();
+    fs::create_dir(dir.join("out")).unwrap();
+    fs::write(dir.join("out/a"), b"existing\n").unwrap();
+    set_mtime(&dir.join("out/a"), existing_mtime);
+    fs::hard_link(dir.join("out/a"), dir.join("out/link")).unwrap();
+}
+
+#[test]
+fn overwrite_policies() {
+    let read = |dir: &TempDir, name: &str| fs::read(dir.path().join("out").join(name)).unwrap();
+
+    // By default the existing file is unlinked, leaving other links to it alone
+    let dir = TempDir::new().unwrap();
+    overwrite_setup(dir.path(), 2_000_000_000, 1_000_000_000);
+    extract_with(dir.path(), &[]).success();
+    assert_eq!(read(&dir, "a"), b"archived\n");
+    assert_eq!(read(&dir, "link"), b"existing\n");
+
+    let dir = TempDir::new().unwrap();
+    overwrite_setup(dir.path(), 2_000_000_000, 1_000_000_000);
+    extract_with(dir.path(), &["--overwrite"]).success();
+    assert_eq!(read(&dir, "a"), b"archived\n");
+    assert_eq!(read(&dir, "link"), b"archived\n");
+
+    let dir = TempDir::new().unwrap();
+    overwrite_setup(dir.path(), 2_000_000_000, 1_000_000_000);
+    extract_with(dir.path(), &["-k"]).failure();
+    assert_eq!(read(&dir, "a"), b"existing\n");
+
+    let dir = TempDir::new().unwrap();
+    overwrite_setup(dir.path(), 2_000_000_000, 1_000_000_000);
+    extract_with(dir.path(), &["--skip-old-files"]).success();
+    assert_eq!(read(&dir, "a"), b"existing\n");
+
+    for &(existing_mtime, expected) in &[
+        (1_000_000_000, &b"archived\n"[..]),
+        (3_000_000_000, &b"existing\n"[..]),
+    ] {
+        let dir = TempDir::new().unwrap();
+        overwrite_setup(dir.path(), 2_000_000_000, existing_mtime);
+        extract_with(dir.path(), &["--keep-newer-files"]).success();
+        assert_eq!(read(&dir, "a"), expected);
+    }
+}
+
+#[test]
+fn symlinks_replace_existing_files() {
+    let dir = TempDir::new().unwrap();
+    let mut builder = Builder::new(File::create(dir.path().join("test.tar")).unwrap());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    builder.append_link(&mut header, "a", "target").unwrap();
+    builder.finish().unwrap();
+    fs::create_dir(dir.path().join("out")).unwrap();
+    fs::write(dir.path().join("out/a"), b"existing\n").unwrap();
+
+    extract_with(dir.path(), &["-k"]).failure();
+    assert_eq!(fs::read(dir.path().join("out/a")).unwrap(), b"existing\n");
+    extract_with(dir.path(), &[]).success();
+    assert_eq!(
+        fs::read_link(dir.path().join("out/a")).unwrap(),
+        Path::new("target")
+    );
+}
+
+#[test]
+fn checkpoints_and_progress() {
+    let dir = TempDir::new().unwrap();
+    // A header, the data and the two end of archive blocks fill exactly three records
+    let contents = (0..3 * 10240 - 3 * 512)
+        .map(|i| i as u8)
+        .collect::<Vec<_>>();
+    fs::write(dir.path().join("a"), &contents).unwrap();
+    tar(dir.path())
+        .args(["cf", "test.tar", "--checkpoint=1", "a"])
+        .assert()
+        .success()
+        .stderr("tar: Write checkpoint 1\ntar: Write checkpoint 2\ntar: Write checkpoint 3\n");
+
+    fs::create_dir(dir.path().join("out")).unwrap();
+    extract_with(dir.path(), &["--checkpoint=2", "--checkpoint-action=dot"])
+        .success()
+        .stderr(".");
+    assert_eq!(fs::read(dir.path().join("out/a")).unwrap(), contents);
+
+    let len = fs::metadata(dir.path().join("test.tar")).unwrap().len();
+    assert_eq!(len, 3 * 10240);
+    extract_with(dir.path(), &["--progress"])
+        .success()
+        .stderr(predicate::str::contains("] ").and(predicate::str::contains("/ 30.0 KiB\n")));
+}
+
+#[test]
+fn sparse_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let contents = write_sparse(&dir.path().join("sparse"));
+    // Data after the last hole instead of a trailing hole, and a file without holes
+    let mut file = File::create(dir.path().join("sparse-end")).unwrap();
+    file.seek(SeekFrom::Start(8192)).unwrap();
+    file.write_all(&[9; 512]).unwrap();
+    drop(file);
+    fs::write(dir.path().join("dense"), [7; 1024]).unwrap();
+    tar(dir.path())
+        .args(["cSf", "test.tar", "sparse", "sparse-end", "dense"])
+        .assert()
+        .success();
+
+    // Other readers see the same members, including the extended sparse headers
+    let mut archive = tar::Archive::new(File::open(dir.path().join("test.tar")).unwrap());
+    let members = archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            (entry.header().entry_type(), data)
+        })
+        .collect::<Vec<_>>();
+    let mut sparse_end = vec![0; 8192];
+    sparse_end.extend_from_slice(&[9; 512]);
+    assert_eq!(
+        members,
+        [
+            (EntryType::GNUSparse, contents.clone()),
+            (EntryType::GNUSparse, sparse_end.clone()),
+            (EntryType::Regular, vec![7; 1024]),
+        ]
+    );
+
+    extract(dir.path());
+    for &(name, expected) in &[("sparse", &contents), ("sparse-end", &sparse_end)] {
+        let path = dir.path().join("out").join(name);
+        assert_eq!(&fs::read(&path).unwrap(), expected);
+        // The holes are recreated rather than written out as zeroes
+        assert!(fs::metadata(&path).unwrap().blocks() * 512 < expected.len() as u64);
+    }
+    assert_eq!(fs::read(dir.path().join("out/dense")).unwrap(), [7; 1024]);
+}
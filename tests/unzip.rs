@@ -0,0 +1,223 @@
+extern crate assert_cmd;
+extern crate crc32fast;
+extern crate predicates;
+extern crate tempfile;
+
+mod common;
+
+use assert_cmd::Command;
+use common::{zip, DIR, FILE, LINK};
+use predicates::prelude::*;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Add an extra field to the central directory record of an archive with a single member
+fn add_central_extra(zip: &mut Vec<u8>, id: u16, data: &[u8]) {
+    let le_u32 = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let record = zip.len() - 22;
+    let directory = le_u32(&zip[record + 16..]) as usize;
+    let name_len = usize::from(u16::from_le_bytes([
+        zip[directory + 28],
+        zip[directory + 29],
+    ]));
+
+    let mut extra = id.to_le_bytes().to_vec();
+    extra.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    extra.extend_from_slice(data);
+    let size = le_u32(&zip[record + 12..]) + extra.len() as u32;
+    zip[record + 12..record + 16].copy_from_slice(&size.to_le_bytes());
+    zip[directory + 30..directory + 32].copy_from_slice(&(extra.len() as u16).to_le_bytes());
+    let pos = directory + 46 + name_len;
+    zip.splice(pos..pos, extra);
+}
+
+/// Write the archive into a new temporary directory, which also holds the `dest` directory
+/// to extract into
+fn setup(members: &[(&str, u16, u32, &[u8])]) -> TempDir {
+    let dir = TempDir::new().expect("temporary directory");
+    fs::write(dir.path().join("test.zip"), zip(members)).expect("write archive");
+    dir
+}
+
+fn unzip(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("unzip").expect("found binary");
+    cmd.current_dir(dir).args(["-d", "dest", "test.zip"]);
+    cmd
+}
+
+#[test]
+fn extracts_files_and_directories() {
+    let dir = setup(&[
+        ("sub/", 0, DIR, b""),
+        ("sub/file.txt", 0, FILE, b"hello\n"),
+        ("top.txt", 0, FILE, b"top\n"),
+    ]);
+    unzip(dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3 extracted, 0 skipped, 0 failed"));
+    assert_eq!(
+        fs::read(dir.path().join("dest/sub/file.txt")).unwrap(),
+        b"hello\n"
+    );
+    assert_eq!(fs::read(dir.path().join("dest/top.txt")).unwrap(), b"top\n");
+}
+
+#[test]
+fn parent_directory_is_refused() {
+    let dir = setup(&[
+        ("../evil.txt", 0, FILE, b"evil\n"),
+        ("sub/../../../evil.txt", 0, FILE, b"evil\n"),
+        ("good.txt", 0, FILE, b"good\n"),
+    ]);
+    unzip(dir.path())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("refusing to extract outside"))
+        .stdout(predicate::str::contains("1 extracted, 0 skipped, 2 failed"));
+    assert!(!dir.path().join("evil.txt").exists());
+    assert!(dir.path().join("dest/good.txt").exists());
+}
+
+#[test]
+fn parent_directory_inside_destination_is_allowed() {
+    let dir = setup(&[("sub/../file.txt", 0, FILE, b"file\n")]);
+    unzip(dir.path()).assert().success();
+    assert!(dir.path().join("dest/file.txt").exists());
+}
+
+#[test]
+fn absolute_path_is_made_relative() {
+    let dir = setup(&[("/abs/file.txt", 0, FILE, b"abs\n")]);
+    unzip(dir.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("stripped absolute path"));
+    assert!(dir.path().join("dest/abs/file.txt").exists());
+}
+
+#[test]
+fn file_through_escaping_link_is_refused() {
+    let dir = setup(&[
+        ("link", 0, LINK, b"../outside"),
+        ("link/evil.txt", 0, FILE, b"evil\n"),
+    ]);
+    fs::create_dir(dir.path().join("outside")).unwrap();
+    unzip(dir.path())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("refusing to extract outside"));
+    assert!(!dir.path().join("outside/evil.txt").exists());
+}
+
+#[test]
+fn directory_through_dangling_link_is_refused() {
+    let dir = setup(&[
+        ("link", 0, LINK, b"../missing"),
+        ("link/sub/", 0, DIR, b""),
+        ("link/sub/evil.txt", 0, FILE, b"evil\n"),
+    ]);
+    unzip(dir.path())
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("1 extracted, 0 skipped, 2 failed"));
+    assert!(!dir.path().join("missing").exists());
+}
+
+#[test]
+fn absolute_link_is_refused() {
+    let outside = TempDir::new().unwrap();
+    let target = outside.path().to_str().unwrap().to_string();
+    let dir = setup(&[
+        ("link", 0, LINK, target.as_bytes()),
+        ("link/evil.txt", 0, FILE, b"evil\n"),
+    ]);
+    unzip(dir.path()).assert().code(2);
+    assert!(!outside.path().join("evil.txt").exists());
+}
+
+#[test]
+fn link_inside_destination_is_followed() {
+    let dir = setup(&[
+        ("sub/", 0, DIR, b""),
+        ("link", 0, LINK, b"sub"),
+        ("link/file.txt", 0, FILE, b"file\n"),
+    ]);
+    unzip(dir.path()).assert().success();
+    assert!(dir.path().join("dest/sub/file.txt").exists());
+}
+
+#[test]
+fn existing_link_is_replaced_not_followed() {
+    let dir = setup(&[("file.txt", 0, FILE, b"new\n")]);
+    fs::write(dir.path().join("target.txt"), b"old\n").unwrap();
+    fs::create_dir(dir.path().join("dest")).unwrap();
+    std::os::unix::fs::symlink("../target.txt", dir.path().join("dest/file.txt")).unwrap();
+    unzip(dir.path()).arg("-o").assert().success();
+    assert_eq!(fs::read(dir.path().join("target.txt")).unwrap(), b"old\n");
+    assert_eq!(
+        fs::read(dir.path().join("dest/file.txt")).unwrap(),
+        b"new\n"
+    );
+}
+
+#[test]
+fn summary_counts_skipped_files() {
+    let dir = setup(&[("file.txt", 0, FILE, b"file\n")]);
+    unzip(dir.path()).assert().success();
+    unzip(dir.path())
+        .arg("-n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 extracted, 1 skipped, 0 failed"));
+}
+
+#[test]
+fn timestamp_before_1970_is_restored() {
+    let dir = TempDir::new().unwrap();
+    let mut zip = zip(&[("old.txt", 0, FILE, b"old\n")]);
+    // Flags with only the modification time, one day before the epoch
+    let mut timestamp = vec![1];
+    timestamp.extend_from_slice(&(-86400i32).to_le_bytes());
+    add_central_extra(&mut zip, 0x5455, &timestamp);
+    fs::write(dir.path().join("test.zip"), zip).unwrap();
+    unzip(dir.path()).assert().success();
+    let metadata = fs::metadata(dir.path().join("dest/old.txt")).unwrap();
+    assert_eq!(metadata.mtime(), -86400);
+}
+
+#[test]
+fn overflowing_header_offset_is_invalid() {
+    let dir = TempDir::new().unwrap();
+    let mut zip = zip(&[("file.txt", 0, FILE, b"file\n")]);
+    let record = zip.len() - 22;
+    let directory = u32::from_le_bytes([
+        zip[record + 16],
+        zip[record + 17],
+        zip[record + 18],
+        zip[record + 19],
+    ]) as usize;
+    // The offset is in the ZIP64 field, and data prepended to the archive is added to it
+    zip[directory + 42..directory + 46].copy_from_slice(&[0xFF; 4]);
+    add_central_extra(&mut zip, 0x0001, &[0xFF; 8]);
+    zip.splice(0..0, b"prepended".iter().cloned());
+    fs::write(dir.path().join("test.zip"), zip).unwrap();
+    unzip(dir.path())
+        .assert()
+        .code(9)
+        .stderr(predicate::str::contains("invalid local header offset"));
+}
+
+#[test]
+fn special_mode_bits_are_not_restored() {
+    let dir = setup(&[
+        ("sub/", 0, 0o041777, b""),
+        ("sub/file", 0, 0o106755, b"file\n"),
+    ]);
+    unzip(dir.path()).assert().success();
+    let mode = |path: &str| fs::metadata(dir.path().join(path)).unwrap().mode() & 0o7777;
+    assert_eq!(mode("dest/sub/file"), 0o755);
+    assert_eq!(mode("dest/sub"), 0o777);
+}
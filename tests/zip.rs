@@ -0,0 +1,176 @@
+extern crate assert_cmd;
+extern crate crc32fast;
+extern crate filetime;
+extern crate predicates;
+extern crate tempfile;
+
+mod common;
+
+use assert_cmd::Command;
+use common::{zip, FILE, FLAG_DESCRIPTOR, FLAG_ENCRYPTED};
+use filetime::FileTime;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// The local headers and data of the members of `test.zip`, everything before its central
+/// directory
+fn members(dir: &Path) -> Vec<u8> {
+    let mut zip = fs::read(dir.join("test.zip")).unwrap();
+    let record = zip.len() - 22;
+    let start = u32::from_le_bytes([
+        zip[record + 16],
+        zip[record + 17],
+        zip[record + 18],
+        zip[record + 19],
+    ]);
+    zip.truncate(start as usize);
+    zip
+}
+
+fn run(name: &str, dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin(name).expect("found binary");
+    cmd.current_dir(dir);
+    cmd
+}
+
+#[test]
+fn adding_keeps_existing_members_unchanged() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), b"a\n").unwrap();
+    fs::write(dir.path().join("b"), "b".repeat(1000)).unwrap();
+    // An odd second, which the MS-DOS time cannot hold
+    let mtime = FileTime::from_unix_time(1_000_000_001, 0);
+    filetime::set_file_mtime(dir.path().join("a"), mtime).unwrap();
+    run("zip", dir.path())
+        .args(["-q", "test.zip", "a", "b"])
+        .assert()
+        .success();
+    let before = members(dir.path());
+
+    fs::write(dir.path().join("c"), b"c\n").unwrap();
+    run("zip", dir.path())
+        .args(["test.zip", "c"])
+        .assert()
+        .success()
+        .stdout("  adding: c (stored 0%)\n");
+    assert!(members(dir.path()).starts_with(&before));
+
+    run("unzip", dir.path())
+        .args(["-q", "-d", "dest", "test.zip"])
+        .assert()
+        .success();
+    let metadata = fs::metadata(dir.path().join("dest/a")).unwrap();
+    assert_eq!(FileTime::from_last_modification_time(&metadata), mtime);
+    assert_eq!(
+        fs::read(dir.path().join("dest/b")).unwrap(),
+        "b".repeat(1000).as_bytes()
+    );
+    assert_eq!(fs::read(dir.path().join("dest/c")).unwrap(), b"c\n");
+}
+
+#[test]
+fn encrypted_member_is_copied() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[("secret", FLAG_ENCRYPTED, FILE, b"not really encrypted")]),
+    )
+    .unwrap();
+    let before = members(dir.path());
+
+    fs::write(dir.path().join("new"), b"new\n").unwrap();
+    run("zip", dir.path())
+        .args(["-q", "test.zip", "new"])
+        .assert()
+        .success();
+    assert!(members(dir.path()).starts_with(&before));
+    run("unzip", dir.path())
+        .args(["-l", "test.zip"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("secret").and(predicate::str::contains("new")));
+}
+
+#[test]
+fn data_descriptor_is_copied() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[
+            ("streamed", FLAG_DESCRIPTOR, FILE, b"streamed\n"),
+            ("after", 0, FILE, b"after\n"),
+        ]),
+    )
+    .unwrap();
+    let before = members(dir.path());
+
+    fs::write(dir.path().join("new"), b"new\n").unwrap();
+    run("zip", dir.path())
+        .args(["-q", "test.zip", "new"])
+        .assert()
+        .success();
+    assert!(members(dir.path()).starts_with(&before));
+    run("unzip", dir.path())
+        .args(["-t", "test.zip"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn delete_moves_following_members() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("test.zip"),
+        zip(&[
+            ("a", 0, FILE, b"a\n"),
+            ("b", FLAG_DESCRIPTOR, FILE, b"b\n"),
+            ("c", 0, FILE, b"c\n"),
+        ]),
+    )
+    .unwrap();
+    run("zip", dir.path())
+        .args(["-d", "test.zip", "b"])
+        .assert()
+        .success()
+        .stdout("deleting: b\n");
+    assert_eq!(
+        members(dir.path()),
+        zip(&[("a", 0, FILE, b"a\n"), ("c", 0, FILE, b"c\n")])[..2 * (30 + 1 + 2)]
+    );
+    run("unzip", dir.path())
+        .args(["-t", "test.zip"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn extended_timestamps_round_trip() {
+    let dir = TempDir::new().unwrap();
+    // Before 1970, and after 2038 where the signed field is clamped
+    let times = [
+        ("old", -100_000_000, -100_000_000),
+        ("new", 3_000_000_000, i64::from(i32::MAX)),
+    ];
+    for &(name, mtime, _) in &times {
+        fs::write(dir.path().join(name), name).unwrap();
+        filetime::set_file_mtime(dir.path().join(name), FileTime::from_unix_time(mtime, 0))
+            .unwrap();
+    }
+    run("zip", dir.path())
+        .args(["-q", "test.zip", "old", "new"])
+        .assert()
+        .success();
+    run("unzip", dir.path())
+        .args(["-q", "-d", "dest", "test.zip"])
+        .assert()
+        .success();
+    for &(name, _, expected) in &times {
+        let meta = fs::metadata(dir.path().join("dest").join(name)).unwrap();
+        assert_eq!(
+            FileTime::from_last_modification_time(&meta).unix_seconds(),
+            expected
+        );
+    }
+}